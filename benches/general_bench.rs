@@ -1,6 +1,9 @@
 use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
 use fastrand::Rng;
-use snad_stack::simulate::{cells::CellType, world::World};
+use snad_stack::simulate::{
+    cells::{Cell, CellType},
+    world::World,
+};
 
 fn general_bench(c: &mut Criterion) {
     const WIDTH: u32 = 1600;
@@ -42,18 +45,21 @@ fn general_bench(c: &mut Criterion) {
     });
 
     group.bench_function("Rendering", |b| {
-        b.iter_batched_ref(
-            || black_box(World::new(WIDTH as i32, HEIGHT as i32, 1)),
-            |world| world.render(&mut pixels),
-            BatchSize::SmallInput,
-        )
+        // Every material, so shaded ones like fire and water are drawn too, not just plain air
+        let mut world = World::new(WIDTH as i32, HEIGHT as i32, 1);
+        let materials = &CellType::ALL[1..];
+        for index in 0..world.grid.len() {
+            let material = materials[fastrand::usize(0..materials.len())];
+            world.grid.set(index, Cell::new(material));
+        }
+        let mut world = black_box(world);
+        b.iter(|| world.render(&mut pixels))
     });
 
     group.bench_function("Placing", |b| {
         b.iter_batched_ref(
             || {
                 let w = World::new(WIDTH as i32, HEIGHT as i32, 1);
-                let p = &mut pixels.clone();
                 let x = rand_range(WIDTH);
                 let y = rand_range(HEIGHT);
 
@@ -61,10 +67,10 @@ fn general_bench(c: &mut Criterion) {
             },
             |(world, pixels, x, y)| {
                 world.draw_thick_line(
-                    { *x },
-                    { *y },
-                    ({ *x } + fastrand::i32(-64..64).max(0)) as i32,
-                    ({ *y } + fastrand::i32(-64..64).max(0)) as i32,
+                    *x,
+                    *y,
+                    *x + fastrand::i32(-64..64).max(0),
+                    *y + fastrand::i32(-64..64).max(0),
                     64,
                     choices[rand_range(choices.len() as u32) as usize],
                     true,
@@ -22,6 +22,7 @@ fn main() {
     let enum_definitions =
         fs::read_to_string("assets/cell-definitions.csv").expect("Failed to read settings file");
     let mut parsed_lines = vec![];
+    let mut decays = vec![];
     for line in enum_definitions.lines() {
         let parts: Vec<_> = line.split(' ').collect();
 
@@ -32,6 +33,22 @@ fn main() {
         let rgb_start = parse_hex(parts[2]).unwrap();
         let rgb_end = parse_hex(parts[3]).unwrap();
 
+        // Optional lifespan range and the material left behind when it runs out
+        let decay = match parts.get(4..7) {
+            Some([min, max, target]) => {
+                let (i, rest) = target.split_at(1);
+                format!(
+                    "Some(({}, {}, CellType::{}{}))",
+                    min.parse::<u16>().expect("Invalid minimum lifespan"),
+                    max.parse::<u16>().expect("Invalid maximum lifespan"),
+                    i.to_uppercase(),
+                    rest
+                )
+            }
+            _ => String::from("None"),
+        };
+        decays.push(decay);
+
         parsed_lines.push((name, character, rgb_start, rgb_end))
     }
 
@@ -43,40 +60,64 @@ fn main() {
         .collect::<Vec<String>>()
         .join("\n");
 
+    let variants = parsed_lines
+        .iter()
+        .map(|(name, _, _, _)| format!("CellType::{name}"))
+        .collect::<Vec<String>>()
+        .join(",");
+
     let colors = parsed_lines
         .iter()
         .map(|(_, _, start, end)| format!("[{start:?}, {end:?}]"))
         .collect::<Vec<String>>()
         .join(",");
 
-    let inputs = parsed_lines
+    let names_lookup = parsed_lines
         .iter()
-        .map(|(name, character, _, _)| {
-            format!("VirtualKeyCode::{character} => *self = CellType::{name}")
-        })
+        .map(|(name, _, _, _)| format!("{:?}", name.to_lowercase()))
         .collect::<Vec<String>>()
         .join(",");
 
+    let hotkeys = parsed_lines
+        .iter()
+        .map(|(_, character, _, _)| format!("{character:?}"))
+        .collect::<Vec<String>>()
+        .join(",");
+
+    let decays = decays.join(",");
+
     let definition = format!(
         "
-        use winit::event::VirtualKeyCode;
         const COLOR_LOOKUP: [[[u8;3];2];{length}] = [{colors}];
-        #[repr(usize)]
+        const NAME_LOOKUP: [&str; {length}] = [{names_lookup}];
+        const HOTKEY_LOOKUP: [&str; {length}] = [{hotkeys}];
+        const DECAY_LOOKUP: [Option<(u16, u16, CellType)>; {length}] = [{decays}];
+        #[repr(u8)]
         #[derive(Clone, Copy, Debug, Eq, PartialEq)]
         pub enum CellType {{
             {names}
         }}
         impl CellType {{
+            pub const ALL: [CellType; {length}] = [{variants}];
             pub fn color(&self) -> &[[u8;3];2]  {{
-                unsafe {{
-                    &COLOR_LOOKUP[(*self as usize)]
-                }}
+                &COLOR_LOOKUP[*self as usize]
+            }}
+            pub fn name(&self) -> &'static str {{
+                NAME_LOOKUP[*self as usize]
+            }}
+            // Name of the default key that selects this material
+            pub fn hotkey(&self) -> &'static str {{
+                HOTKEY_LOOKUP[*self as usize]
+            }}
+            // Lifespan range of a new cell and what it turns into when that runs out
+            pub fn decay(&self) -> Option<(u16, u16, CellType)> {{
+                DECAY_LOOKUP[*self as usize]
             }}
-            pub fn switch_if_valid(&mut self, code: VirtualKeyCode) {{
-                match code {{
-                    {inputs},
-                    _ => ()
-                }}         
+            pub fn from_name(name: &str) -> Option<CellType> {{
+                Self::ALL
+                    .iter()
+                    .find(|material| material.name().eq_ignore_ascii_case(name))
+                    .copied()
             }}
         }}
         "
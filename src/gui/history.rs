@@ -0,0 +1,44 @@
+use std::collections::VecDeque;
+
+use crate::simulate::{grid::Grid, world::World};
+
+// Copies of the grid taken before each stroke, so strokes can be undone
+pub struct History {
+    snapshots: VecDeque<(usize, usize, Grid)>,
+    limit: usize,
+    drawing: bool,
+}
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            limit,
+            drawing: false,
+        }
+    }
+    // Call every frame, a snapshot is taken on the first frame of each stroke
+    pub fn track_stroke(&mut self, world: &World, drawing: bool) {
+        if drawing && !self.drawing {
+            self.push(world);
+        }
+        self.drawing = drawing;
+    }
+    pub fn push(&mut self, world: &World) {
+        if self.snapshots.len() == self.limit {
+            self.snapshots.pop_front();
+        }
+        self.snapshots
+            .push_back((world.width, world.height, world.grid.clone()));
+    }
+    // Snapshots from before a resize no longer fit the grid, so they are skipped
+    pub fn undo(&mut self, world: &mut World) -> bool {
+        while let Some((width, height, grid)) = self.snapshots.pop_back() {
+            if width == world.width && height == world.height {
+                world.grid = grid;
+                world.grid_replaced();
+                return true;
+            }
+        }
+        false
+    }
+}
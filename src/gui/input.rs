@@ -1,8 +1,19 @@
-use crate::simulate::{cells::CellType, world::World};
+use crate::simulate::{
+    api::DIRECTIONS,
+    boundary::Edge,
+    cells::{Cell, CellType},
+    debug::DebugView,
+    gravity::Gravity,
+    world::World,
+};
 
+use std::{collections::HashMap, fmt, fs, mem, path::Path};
 use winit::{
     dpi::{PhysicalPosition, PhysicalSize},
-    event::{ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent},
+    event::{
+        ElementState, Event, MouseButton, MouseScrollDelta, Touch, TouchPhase, VirtualKeyCode,
+        WindowEvent,
+    },
     window::Window,
 };
 #[derive(Clone, Copy, Default, PartialEq, Debug)]
@@ -28,6 +39,415 @@ impl CenterLocation {
     }
 }
 
+// Everything the keyboard (and held mouse buttons) can trigger
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    SelectMaterial(CellType),
+    BrushGrow,
+    BrushShrink,
+    TogglePause,
+    Step,
+    Save,
+    Load,
+    Undo,
+    Clear,
+    CycleDebugView,
+    ToggleLighting,
+    CycleLightingQuality,
+    WindLeft,
+    WindRight,
+    WindUp,
+    WindDown,
+    Calm,
+    RotateFan,
+    EmitterMaterial,
+    EmitterSlower,
+    EmitterFaster,
+    RotateGravity,
+    GravityWeaker,
+    GravityStronger,
+    CycleBoundary(Edge),
+    ToggleInspector,
+    InspectorPrevField,
+    InspectorNextField,
+    InspectorDecrease,
+    InspectorIncrease,
+    Paint,
+    Erase,
+    Pick,
+}
+// How each action without an argument is named in keymaps and recordings
+const ACTION_NAMES: [(&str, Action); 31] = [
+    ("brush_grow", Action::BrushGrow),
+    ("brush_shrink", Action::BrushShrink),
+    ("pause", Action::TogglePause),
+    ("step", Action::Step),
+    ("save", Action::Save),
+    ("load", Action::Load),
+    ("undo", Action::Undo),
+    ("clear", Action::Clear),
+    ("debug_view", Action::CycleDebugView),
+    ("lighting", Action::ToggleLighting),
+    ("lighting_quality", Action::CycleLightingQuality),
+    ("wind_left", Action::WindLeft),
+    ("wind_right", Action::WindRight),
+    ("wind_up", Action::WindUp),
+    ("wind_down", Action::WindDown),
+    ("calm", Action::Calm),
+    ("rotate_fan", Action::RotateFan),
+    ("emitter_material", Action::EmitterMaterial),
+    ("emitter_slower", Action::EmitterSlower),
+    ("emitter_faster", Action::EmitterFaster),
+    ("gravity", Action::RotateGravity),
+    ("gravity_weaker", Action::GravityWeaker),
+    ("gravity_stronger", Action::GravityStronger),
+    ("inspector", Action::ToggleInspector),
+    ("inspector_prev_field", Action::InspectorPrevField),
+    ("inspector_next_field", Action::InspectorNextField),
+    ("inspector_decrease", Action::InspectorDecrease),
+    ("inspector_increase", Action::InspectorIncrease),
+    ("paint", Action::Paint),
+    ("erase", Action::Erase),
+    ("pick", Action::Pick),
+];
+// How much each wind key press changes the wind by
+const WIND_STEP: f32 = 0.1;
+const GRAVITY_STEP: f32 = 0.25;
+// Largest brush radius, in cells. Drawing is quadratic in the radius, so bigger brushes stall
+pub const MAX_BRUSH_SIZE: f32 = 16.0;
+
+impl Action {
+    pub fn parse(name: &str, argument: Option<&str>) -> Option<Self> {
+        match name {
+            "material" => Some(Action::SelectMaterial(CellType::from_name(argument?)?)),
+            "boundary" => Some(Action::CycleBoundary(Edge::from_name(argument?)?)),
+            _ => ACTION_NAMES
+                .iter()
+                .find(|(action_name, _)| *action_name == name)
+                .map(|(_, action)| *action),
+        }
+    }
+    // Changes the world's settings if this is an action that does, with `material` being whatever
+    // is selected. False for anything else, which is left to the caller
+    pub fn apply(self, world: &mut World, material: CellType) -> bool {
+        match self {
+            Action::ToggleLighting => world.lighting.enabled = !world.lighting.enabled,
+            Action::CycleLightingQuality => world.lighting.cycle_quality(),
+            Action::WindLeft => world.wind.0 -= WIND_STEP,
+            Action::WindRight => world.wind.0 += WIND_STEP,
+            Action::WindUp => world.wind.1 += WIND_STEP,
+            Action::WindDown => world.wind.1 -= WIND_STEP,
+            Action::Calm => world.wind = (0.0, 0.0),
+            Action::RotateFan => {
+                world.fan_direction = (world.fan_direction + 1) % DIRECTIONS.len() as u8
+            }
+            Action::EmitterMaterial => {
+                // New emitters produce whatever material is selected
+                world.emitter_material = material;
+                log::info!("Emitters produce {material:?}");
+            }
+            Action::EmitterSlower | Action::EmitterFaster => {
+                let rate = &mut world.emitter_rate;
+                *rate = match self {
+                    Action::EmitterSlower => rate.saturating_sub(1).max(1),
+                    _ => (*rate + 1).min(Cell::MAX_RATE),
+                };
+                log::info!("Emitters fire {rate} ticks in {}", Cell::MAX_RATE);
+            }
+            Action::RotateGravity => {
+                let gravity = &mut world.gravity;
+                gravity.direction = gravity.direction.next();
+                // What holds bodies up depends on which way is down
+                world.bodies_dirty = true;
+            }
+            Action::GravityWeaker => {
+                let gravity = &mut world.gravity;
+                gravity.strength = (gravity.strength - GRAVITY_STEP).max(0.0);
+            }
+            Action::GravityStronger => {
+                let gravity = &mut world.gravity;
+                gravity.strength = (gravity.strength + GRAVITY_STEP).min(Gravity::MAX_STRENGTH);
+            }
+            Action::CycleBoundary(edge) => {
+                // Source edges spill whatever material is selected
+                let boundary = world.boundaries.get_mut(edge);
+                *boundary = boundary.next(material);
+                log::info!("{edge:?} edge is now {boundary:?}");
+            }
+            _ => return false,
+        }
+        let (wind_x, wind_y) = world.wind;
+        world.wind = (wind_x.clamp(-1.0, 1.0), wind_y.clamp(-1.0, 1.0));
+        true
+    }
+    // Actions that change the simulated world rather than how it is shown or drawn on. A client
+    // following a server ignores them, its copy would only fall out of step
+    pub fn changes_world(self) -> bool {
+        matches!(
+            self,
+            Action::Step
+                | Action::Load
+                | Action::Undo
+                | Action::Clear
+                | Action::WindLeft
+                | Action::WindRight
+                | Action::WindUp
+                | Action::WindDown
+                | Action::Calm
+                | Action::RotateFan
+                | Action::EmitterMaterial
+                | Action::EmitterSlower
+                | Action::EmitterFaster
+                | Action::RotateGravity
+                | Action::GravityWeaker
+                | Action::GravityStronger
+                | Action::CycleBoundary(_)
+        )
+    }
+}
+// Written the way `Action::parse` reads it
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::SelectMaterial(material) => write!(f, "material {}", material.name()),
+            Action::CycleBoundary(edge) => write!(f, "boundary {}", edge.name()),
+            action => {
+                let (name, _) = ACTION_NAMES
+                    .iter()
+                    .find(|(_, named)| named == action)
+                    .expect("Every action without an argument is named");
+                f.write_str(name)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        fn parse_binding(name: &str) -> Option<Binding> {
+            match name {
+                "MouseLeft" => Some(Binding::Mouse(MouseButton::Left)),
+                "MouseMiddle" => Some(Binding::Mouse(MouseButton::Middle)),
+                "MouseRight" => Some(Binding::Mouse(MouseButton::Right)),
+                $(stringify!($key) => Some(Binding::Key(VirtualKeyCode::$key)),)*
+                _ => None,
+            }
+        }
+    };
+}
+key_names!(
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    Key0,
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    Escape,
+    Tab,
+    Space,
+    Return,
+    Back,
+    Delete,
+    Insert,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Left,
+    Up,
+    Right,
+    Down,
+    Minus,
+    Equals,
+    LBracket,
+    RBracket,
+    Semicolon,
+    Apostrophe,
+    Comma,
+    Period,
+    Slash,
+    Backslash,
+    Grave,
+    NumpadAdd,
+    NumpadSubtract,
+);
+
+const DEFAULT_COMMANDS: &str = "
+MouseLeft paint
+MouseRight erase
+MouseMiddle pick
+RBracket brush_grow
+LBracket brush_shrink
+Space pause
+Period step
+F5 save
+F9 load
+Z undo
+Delete clear
+Tab debug_view
+F3 lighting
+F4 lighting_quality
+Home wind_left
+End wind_right
+PageUp wind_up
+PageDown wind_down
+Insert calm
+Backslash rotate_fan
+Semicolon emitter_material
+Comma emitter_slower
+Slash emitter_faster
+F6 gravity
+F7 gravity_weaker
+F8 gravity_stronger
+Key1 boundary top
+Key2 boundary bottom
+Key3 boundary left
+Key4 boundary right
+F2 inspector
+Up inspector_prev_field
+Down inspector_next_field
+Left inspector_decrease
+Right inspector_increase
+";
+
+const MOUSE_BUTTONS: [MouseButton; 3] =
+    [MouseButton::Left, MouseButton::Middle, MouseButton::Right];
+
+// Maps keys and mouse buttons to actions. Lines look like `<binding> <action> [argument]`
+pub struct Keymap {
+    bindings: HashMap<Binding, Action>,
+}
+impl Keymap {
+    // Material hotkeys come from the second column of the cell definitions
+    pub fn defaults() -> (Self, Vec<String>) {
+        let mut text = String::from(DEFAULT_COMMANDS);
+        for material in CellType::ALL {
+            text.push_str(&format!(
+                "{} material {}\n",
+                material.hotkey(),
+                material.name()
+            ));
+        }
+        let mut keymap = Self {
+            bindings: HashMap::new(),
+        };
+        let problems = keymap.parse(&text);
+        (keymap, problems)
+    }
+    // Bindings in the file are layered over the defaults. A missing file just means defaults
+    pub fn load(path: impl AsRef<Path>) -> (Self, Vec<String>) {
+        let (mut keymap, mut problems) = Self::defaults();
+        if let Ok(text) = fs::read_to_string(&path) {
+            problems.extend(keymap.parse(&text));
+        }
+        problems.extend(keymap.missing_buttons());
+        (keymap, problems)
+    }
+    // Returns a description of every line that could not be used or overrode another binding
+    pub fn parse(&mut self, text: &str) -> Vec<String> {
+        let mut problems = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let (Some(binding_name), Some(action_name)) = (words.next(), words.next()) else {
+                problems.push(format!("Line {}: expected `<binding> <action>`", index + 1));
+                continue;
+            };
+            let Some(binding) = parse_binding(binding_name) else {
+                problems.push(format!("Line {}: unknown key {binding_name}", index + 1));
+                continue;
+            };
+            let Some(action) = Action::parse(action_name, words.next()) else {
+                problems.push(format!("Line {}: unknown action {line}", index + 1));
+                continue;
+            };
+            if let Some(previous) = self.bindings.insert(binding, action) {
+                if previous != action {
+                    problems.push(format!(
+                        "Line {}: {binding_name} was bound to {previous:?}, now {action:?}",
+                        index + 1
+                    ));
+                }
+            }
+        }
+        problems
+    }
+    pub fn action(&self, binding: Binding) -> Option<Action> {
+        self.bindings.get(&binding).copied()
+    }
+    // Describes every mouse action that was left without a button, like after rebinding it to
+    // another action. Any number of buttons can share one
+    fn missing_buttons(&self) -> Vec<String> {
+        [Action::Paint, Action::Erase, Action::Pick]
+            .into_iter()
+            .filter(|action| {
+                MOUSE_BUTTONS
+                    .iter()
+                    .all(|button| self.action(Binding::Mouse(*button)) != Some(*action))
+            })
+            .map(|action| format!("Nothing is bound to {action}"))
+            .collect()
+    }
+}
+
+// Two fingers on the screen, either pinching to resize or tapping to toggle erase
+struct TouchGesture {
+    start_distance: f32,
+    start_size: f32,
+    moved: bool,
+}
+
 // A Useful abstraction for winit event_loop events
 pub struct InputHelper {
     mouse_states: [bool; 3],
@@ -36,13 +456,24 @@ pub struct InputHelper {
     pub current_mouse: Option<PhysicalPosition<f32>>,
     selection_size: f32,
     pub material: CellType,
+    pub debug_view: DebugView,
+    pub paused: bool,
+    keymap: Keymap,
+    actions: Vec<Action>,
+    pub erase_mode: bool,
+    touches: HashMap<u64, PhysicalPosition<f32>>,
+    touch_painting: bool,
+    gesture: Option<TouchGesture>,
+    pressure: Option<f32>,
     env_density: u32,
     current_window_size: PhysicalSize<u32>,
     prev_center: CenterLocation,
     curr_center: CenterLocation,
+    // Size of a grid that doesn't follow the window, like a server's. It is drawn scaled to fit
+    fixed_grid: Option<PhysicalSize<u32>>,
 }
 impl InputHelper {
-    pub fn new(env_density: u32, win: &Window) -> Self {
+    pub fn new(env_density: u32, win: &Window, keymap: Keymap) -> Self {
         let current_window_size = win.inner_size();
         Self {
             mouse_states: [false; 3],
@@ -50,11 +481,21 @@ impl InputHelper {
             current_mouse: None,
             selection_size: 8.0,
             material: CellType::Sand,
-            max_size: 16.0,
+            debug_view: DebugView::Normal,
+            paused: false,
+            keymap,
+            actions: vec![],
+            erase_mode: false,
+            touches: HashMap::new(),
+            touch_painting: false,
+            gesture: None,
+            pressure: None,
+            max_size: MAX_BRUSH_SIZE,
             env_density,
             current_window_size,
             prev_center: CenterLocation::new(),
             curr_center: CenterLocation::new(),
+            fixed_grid: None,
         }
     }
     // Called for every event which comes from winit's event loop
@@ -62,8 +503,12 @@ impl InputHelper {
         if let Event::WindowEvent { event, .. } = events {
             match event {
                 WindowEvent::KeyboardInput { input, .. } => {
-                    if let Some(key_code) = input.virtual_keycode {
-                        self.material.switch_if_valid(key_code)
+                    if let (Some(key_code), ElementState::Pressed) =
+                        (input.virtual_keycode, input.state)
+                    {
+                        if let Some(action) = self.keymap.action(Binding::Key(key_code)) {
+                            self.perform(action);
+                        }
                     }
                 }
                 WindowEvent::MouseInput { state, button, .. } => {
@@ -77,18 +522,21 @@ impl InputHelper {
                 WindowEvent::MouseWheel { delta, .. } => {
                     // Touchpad/trackpad
                     if let MouseScrollDelta::PixelDelta(pos) = delta {
-                        self.selection_size -= pos.y as f32 * self.max_size / 2000.0;
+                        self.resize_selection(-pos.y as f32 * self.max_size / 2000.0);
                     }
                     // Scroll wheel
                     if let MouseScrollDelta::LineDelta(_, y) = delta {
-                        self.selection_size -= y * self.max_size / 10.0;
+                        self.resize_selection(-y * self.max_size / 10.0);
                     }
-                    self.selection_size = self.selection_size.clamp(1.0, self.max_size);
                 }
+                WindowEvent::Touch(touch) => self.touch_event(touch),
                 WindowEvent::Resized(size) => {
                     self.current_window_size = *size;
                     self.curr_center = CenterLocation::from(size, self.env_density);
-                    self.max_size = (size.width.max(size.width) / self.env_density) as f32;
+                    // No wider than the world either
+                    let width = (size.width / self.env_density).max(1) as f32;
+                    self.max_size = width.min(MAX_BRUSH_SIZE);
+                    self.selection_size = self.selection_size.min(self.max_size);
                 }
                 _ => {}
             }
@@ -99,6 +547,90 @@ impl InputHelper {
             self.prev_center = self.curr_center;
         }
     }
+    // One finger paints, two fingers pinch to resize the brush or tap to toggle erasing
+    fn touch_event(&mut self, touch: &Touch) {
+        let location = touch.location.cast::<f32>();
+        match touch.phase {
+            TouchPhase::Started => {
+                self.touches.insert(touch.id, location);
+                if self.touches.len() == 1 {
+                    self.touch_painting = true;
+                    self.current_mouse = Some(location);
+                    self.previous = Some(location);
+                } else {
+                    self.touch_painting = false;
+                    self.gesture = self.pinch_distance().map(|distance| TouchGesture {
+                        start_distance: distance.max(1.0),
+                        start_size: self.selection_size,
+                        moved: false,
+                    });
+                }
+            }
+            TouchPhase::Moved => {
+                self.touches.insert(touch.id, location);
+                if self.touch_painting {
+                    self.current_mouse = Some(location);
+                }
+                if let (Some(distance), Some(gesture)) = (self.pinch_distance(), &mut self.gesture)
+                {
+                    gesture.moved |= (distance - gesture.start_distance).abs() > 24.0;
+                    let size = gesture.start_size * distance / gesture.start_distance;
+                    if gesture.moved {
+                        self.selection_size = size.clamp(1.0, self.max_size);
+                    }
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touches.remove(&touch.id);
+                if let Some(gesture) = self.gesture.take() {
+                    if !gesture.moved && touch.phase == TouchPhase::Ended {
+                        self.erase_mode = !self.erase_mode;
+                    }
+                }
+                if self.touches.is_empty() {
+                    self.touch_painting = false;
+                }
+            }
+        }
+        // Pens and some touch screens report how hard they are pressed
+        self.pressure = match touch.force {
+            Some(force) if self.touch_painting => Some(force.normalized().clamp(0.1, 1.0) as f32),
+            _ => None,
+        };
+    }
+    fn pinch_distance(&self) -> Option<f32> {
+        let mut points = self.touches.values();
+        let (a, b) = (points.next()?, points.next()?);
+        Some(((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt())
+    }
+    // Actions which only touch the input state are handled here, the rest are queued for the
+    // event loop to pick up with `take_actions`
+    pub fn perform(&mut self, action: Action) {
+        match action {
+            Action::SelectMaterial(material) => self.material = material,
+            Action::BrushGrow => self.resize_selection(self.max_size / 10.0),
+            Action::BrushShrink => self.resize_selection(-self.max_size / 10.0),
+            Action::TogglePause => self.paused = !self.paused,
+            Action::CycleDebugView => self.debug_view = self.debug_view.next(),
+            _ => self.actions.push(action),
+        }
+    }
+    pub fn take_actions(&mut self) -> Vec<Action> {
+        mem::take(&mut self.actions)
+    }
+    fn resize_selection(&mut self, amount: f32) {
+        self.selection_size = (self.selection_size + amount).clamp(1.0, self.max_size);
+    }
+    pub fn set_selection_size(&mut self, size: f32) {
+        self.selection_size = size.clamp(1.0, self.max_size);
+    }
+    // The brush size as chosen, before pen pressure scales it
+    pub fn chosen_selection_size(&self) -> f32 {
+        self.selection_size
+    }
+    pub fn max_selection_size(&self) -> f32 {
+        self.max_size
+    }
     fn mouse_button_to_int(button: &MouseButton) -> usize {
         match button {
             MouseButton::Left => 0,
@@ -111,16 +643,69 @@ impl InputHelper {
         let index = Self::mouse_button_to_int(&button);
         self.mouse_states[index]
     }
+    // Whether any mouse button bound to the action is held
+    pub fn holding(&self, action: Action) -> bool {
+        MOUSE_BUTTONS.iter().any(|button| {
+            self.mouse_states[Self::mouse_button_to_int(button)]
+                && self.keymap.action(Binding::Mouse(*button)) == Some(action)
+        })
+    }
+    // Material the brush should place this frame, if any button is held
+    pub fn brush_material(&mut self) -> Option<CellType> {
+        if self.holding(Action::Paint) || self.touch_painting {
+            if self.erase_mode {
+                Some(CellType::Air)
+            } else {
+                Some(self.material)
+            }
+        } else if self.holding(Action::Erase) {
+            Some(CellType::Air)
+        } else {
+            None
+        }
+    }
+    // Switch to the material under the cursor while the pick button is held
+    pub fn eyedropper(&mut self, world: &World) {
+        if !self.holding(Action::Pick) {
+            return;
+        }
+        if let Some(position) = self.hovered_cell(world) {
+            let material = world.material(position.y as usize * world.width + position.x as usize);
+            if material != CellType::None {
+                self.material = material;
+            }
+        }
+    }
     pub fn selection_size(&mut self) -> isize {
-        self.selection_size as isize
+        let pressure = self.pressure.unwrap_or(1.0);
+        (self.selection_size * pressure).max(1.0) as isize
+    }
+    pub fn fix_grid_size(&mut self, size: PhysicalSize<u32>) {
+        self.fixed_grid = Some(size);
     }
-    fn convert_position(&self, position: PhysicalPosition<f32>) -> PhysicalPosition<i32> {
-        let d = self.env_density as i32;
+    // Converts window pixels into cell coordinates, the grid is centered when the window size is
+    // not a multiple of the density
+    pub fn convert_position(&self, position: PhysicalPosition<f32>) -> PhysicalPosition<i32> {
         let position = position.cast::<i32>();
         let curr = self.current_window_size;
+        // Fixed grids are scaled by the largest whole number that fits, like `pixels` draws them
+        let (scale, grid) = match self.fixed_grid {
+            Some(grid) => {
+                let scale = (curr.width / grid.width.max(1))
+                    .min(curr.height / grid.height.max(1))
+                    .max(1);
+                (scale, grid)
+            }
+            None => {
+                let d = self.env_density;
+                (d, PhysicalSize::new(curr.width / d, curr.height / d))
+            }
+        };
+        let offset_x = (curr.width as i32 - (grid.width * scale) as i32) / 2;
+        let offset_y = (curr.height as i32 - (grid.height * scale) as i32) / 2;
         PhysicalPosition::new(
-            (position.x - ((curr.width % self.env_density) / 2) as i32) / d,
-            (position.y - ((curr.height % self.env_density) / 2) as i32) / d,
+            (position.x - offset_x) / scale as i32,
+            (position.y - offset_y) / scale as i32,
         )
     }
     // An easy way to get cell coordinates (u32) from mouse position (f32)
@@ -141,6 +726,15 @@ impl InputHelper {
             _ => None,
         }
     }
+    // The cell currently under the mouse, if the mouse is over the grid
+    pub fn hovered_cell(&self, world: &World) -> Option<PhysicalPosition<i32>> {
+        let position = self.convert_position(self.current_mouse?);
+        let in_bounds = position.x >= 0
+            && position.y >= 0
+            && (position.x as usize) < world.width
+            && (position.y as usize) < world.height;
+        in_bounds.then_some(position)
+    }
 
     pub fn resized(&mut self) -> (PhysicalSize<u32>, Option<CenterLocation>) {
         let diff = self.curr_center.difference(self.prev_center);
@@ -152,3 +746,56 @@ impl InputHelper {
         (self.current_window_size.cast::<u32>(), (diff))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty() -> Keymap {
+        Keymap {
+            bindings: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn keymaps_report_lines_they_cannot_use() {
+        let mut keymap = empty();
+        let problems = keymap.parse("Q pause\nNoSuchKey pause\nQ fly\nQ material mithril\nQ\n");
+        assert_eq!(problems.len(), 4, "{problems:?}");
+        assert!(problems[0].starts_with("Line 2: unknown key"));
+        assert!(problems[1].starts_with("Line 3: unknown action"));
+        assert!(problems[2].starts_with("Line 4: unknown action"));
+        assert!(problems[3].starts_with("Line 5: expected"));
+        // Comments and blank lines are fine, and nothing above replaced the first binding
+        assert!(keymap.parse("# just a comment\n\n").is_empty());
+        let q = Binding::Key(VirtualKeyCode::Q);
+        assert_eq!(keymap.action(q), Some(Action::TogglePause));
+    }
+
+    #[test]
+    fn keymaps_report_conflicting_bindings() {
+        let mut keymap = empty();
+        let problems = keymap.parse("Q pause\nQ pause\nQ step\n");
+        assert_eq!(problems.len(), 1, "{problems:?}");
+        assert!(problems[0].starts_with("Line 3: Q was bound to TogglePause"));
+        assert_eq!(
+            keymap.action(Binding::Key(VirtualKeyCode::Q)),
+            Some(Action::Step)
+        );
+    }
+
+    #[test]
+    fn rebinding_a_mouse_button_replaces_its_default() {
+        let (mut keymap, problems) = Keymap::defaults();
+        assert!(problems.is_empty(), "{problems:?}");
+        assert!(keymap.missing_buttons().is_empty());
+
+        let problems = keymap.parse("MouseMiddle erase\n");
+        assert_eq!(problems.len(), 1, "{problems:?}");
+        let button = |button| keymap.action(Binding::Mouse(button));
+        assert_eq!(button(MouseButton::Middle), Some(Action::Erase));
+        assert_eq!(button(MouseButton::Right), Some(Action::Erase));
+        assert_eq!(button(MouseButton::Left), Some(Action::Paint));
+        assert_eq!(keymap.missing_buttons(), ["Nothing is bound to pick"]);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::simulate::{
+    api::DIRECTIONS,
+    cells::{Cell, CellType},
+    world::World,
+};
+
+use winit::{
+    dpi::PhysicalPosition,
+    event::{Event, WindowEvent},
+};
+
+use super::input::{Action, InputHelper};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Material,
+    Red,
+    Green,
+    Blue,
+    Health,
+    Lifespan,
+    Charge,
+    Direction,
+    Emits,
+    Rate,
+    Updated,
+    Discolored,
+    Selected,
+}
+impl Field {
+    const ORDER: [Field; 13] = [
+        Field::Material,
+        Field::Red,
+        Field::Green,
+        Field::Blue,
+        Field::Health,
+        Field::Lifespan,
+        Field::Charge,
+        Field::Direction,
+        Field::Emits,
+        Field::Rate,
+        Field::Updated,
+        Field::Discolored,
+        Field::Selected,
+    ];
+    fn value(self, cell: &Cell) -> String {
+        match self {
+            Field::Material => format!("{:?}", cell.material),
+            Field::Red => cell.rgb[0].to_string(),
+            Field::Green => cell.rgb[1].to_string(),
+            Field::Blue => cell.rgb[2].to_string(),
+            Field::Health => cell.state.health.to_string(),
+            Field::Lifespan => cell.state.lifespan.to_string(),
+            Field::Charge => cell.state.charge.to_string(),
+            Field::Direction => cell.state.direction.to_string(),
+            Field::Emits => format!("{:?}", cell.state.emitted()),
+            Field::Rate => cell.state.rate.to_string(),
+            Field::Updated => cell.state.updated.to_string(),
+            Field::Discolored => cell.state.discolored.to_string(),
+            Field::Selected => cell.state.selected.to_string(),
+        }
+    }
+    fn edit(self, cell: &mut Cell, delta: i32) {
+        match self {
+            // A different material starts out as a new cell of it, with its own lifespan
+            Field::Material => *cell = Cell::new(cycle_material(cell.material, delta)),
+            Field::Red => cell.rgb[0] = cell.rgb[0].wrapping_add(delta as u8),
+            Field::Green => cell.rgb[1] = cell.rgb[1].wrapping_add(delta as u8),
+            Field::Blue => cell.rgb[2] = cell.rgb[2].wrapping_add(delta as u8),
+            Field::Health => cell.state.health = cell.state.health.wrapping_add(delta as u16),
+            Field::Lifespan => cell.state.lifespan = cell.state.lifespan.wrapping_add(delta as u16),
+            Field::Charge => cell.state.charge = cell.state.charge.wrapping_add(delta as u8),
+            Field::Direction => {
+                cell.state.direction =
+                    (cell.state.direction as i32 + delta).rem_euclid(DIRECTIONS.len() as i32) as u8
+            }
+            Field::Emits => cell.state.emits = cycle_material(cell.state.emitted(), delta) as u8,
+            Field::Rate => {
+                cell.state.rate =
+                    (cell.state.rate as i32 + delta).clamp(0, Cell::MAX_RATE as i32) as u8
+            }
+            Field::Updated => cell.state.updated = cell.state.updated.wrapping_add(delta as u8),
+            Field::Discolored => cell.state.discolored = !cell.state.discolored,
+            Field::Selected => cell.state.selected = !cell.state.selected,
+        }
+    }
+}
+
+fn cycle_material(material: CellType, delta: i32) -> CellType {
+    let count = CellType::ALL.len() as i32;
+    let index = CellType::ALL
+        .iter()
+        .position(|other| *other == material)
+        .unwrap_or(0) as i32;
+    CellType::ALL[(index + delta).rem_euclid(count) as usize]
+}
+
+// Shows the full record of the cell under the cursor, and lets it be edited while the world is
+// paused and not following a server
+pub struct Inspector {
+    pub enabled: bool,
+    pinned: Option<PhysicalPosition<i32>>,
+    field: usize,
+    shift: bool,
+    edits: Vec<i32>,
+}
+impl Inspector {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            pinned: None,
+            field: 0,
+            shift: false,
+            edits: vec![],
+        }
+    }
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.pinned = None;
+        self.edits.clear();
+    }
+    // Called for every event which comes from winit's event loop
+    pub fn hook_events(&mut self, events: &Event<()>) {
+        if let Event::WindowEvent {
+            event: WindowEvent::ModifiersChanged(modifiers),
+            ..
+        } = events
+        {
+            self.shift = modifiers.shift();
+        }
+    }
+    pub fn perform(&mut self, action: Action, editable: bool) {
+        let step = if self.shift { 16 } else { 1 };
+        let fields = Field::ORDER.len();
+        match action {
+            Action::ToggleInspector => self.toggle(),
+            _ if !self.enabled || !editable => {}
+            Action::InspectorPrevField => self.field = (self.field + fields - 1) % fields,
+            Action::InspectorNextField => self.field = (self.field + 1) % fields,
+            Action::InspectorDecrease => self.edits.push(-step),
+            Action::InspectorIncrease => self.edits.push(step),
+            _ => {}
+        }
+    }
+    // Applies queued edits and describes the inspected cell, None when there is nothing to show
+    pub fn update(
+        &mut self,
+        world: &mut World,
+        controller: &mut InputHelper,
+        editable: bool,
+    ) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+        let hovered = controller.hovered_cell(world);
+        if controller.holding(Action::Paint) {
+            self.pinned = hovered;
+        }
+        // Pinned cells can fall outside the grid after a resize
+        let position = self
+            .pinned
+            .filter(|pin| (pin.x as usize) < world.width && (pin.y as usize) < world.height)
+            .or(hovered)?;
+        let index = position.y as usize * world.width + position.x as usize;
+        let mut cell = world.grid.get(index);
+
+        let field = Field::ORDER[self.field];
+        if editable && !self.edits.is_empty() {
+            for delta in self.edits.drain(..) {
+                field.edit(&mut cell, delta);
+            }
+            if world.grid.material(index).rigid() || cell.material.rigid() {
+                world.bodies_dirty = true;
+            }
+            world.grid.set(index, cell);
+        } else {
+            self.edits.clear();
+        }
+
+        let mut text = format!("({}, {})", position.x, position.y);
+        for (index, current) in Field::ORDER.iter().enumerate() {
+            let value = current.value(&cell);
+            if editable && index == self.field {
+                text.push_str(&format!(" [{current:?}: {value}]"));
+            } else {
+                text.push_str(&format!(" {current:?}: {value}"));
+            }
+        }
+        if self.pinned.is_some() {
+            text.push_str(" (pinned)");
+        }
+        Some(text)
+    }
+}
@@ -1,2 +1,7 @@
+pub mod history;
 pub mod input;
+pub mod inspector;
+pub mod recording;
 pub mod run;
+#[cfg(target_arch = "wasm32")]
+pub mod web;
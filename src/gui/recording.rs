@@ -0,0 +1,309 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    net::protocol::Stroke,
+    simulate::{cells::CellType, world::World},
+};
+
+use super::input::{Action, CenterLocation};
+
+// A recording is a text file with a header, then one event per line as `<tick> <event>`:
+//   snad-recording 1
+//   seed <seed>
+//   size <width> <height>
+//   <tick> simulate <steps> <times>
+//   <tick> stroke <x1> <y1> <x2> <y2> <radius> <material>
+//   <tick> material <name>
+//   <tick> resize <width> <height>
+//   <tick> action <action> [argument]
+//   <tick> grid <seed> <save as hex>
+//   <tick> end <checksum>
+// Simulation runs on one thread from the seed, so playing the events back in order rebuilds the
+// same grid. Grids are swapped in different ways that use up different amounts of randomness,
+// so both sides reseed after one. Edits made with the inspector aren't recorded
+const HEADER: &str = "snad-recording 1";
+// Runs of simulate calls are written as one line, but not so long that a crash loses much
+const MAX_MERGED_SIMULATES: u32 = 600;
+// The game only ever simulates a couple of steps at a time, longer calls are a corrupt file
+const MAX_SIMULATE_STEPS: u16 = 16;
+
+// Puts a world into the state every recording starts from
+fn start(world: &mut World, seed: u64) {
+    fastrand::seed(seed);
+    world.seed = seed;
+    world.single_threaded = true;
+    world.time = 0;
+    world.ticks = 0;
+    world.clear();
+}
+
+// FNV-1a of the world's save, written at the end so a replay can tell whether it diverged
+fn checksum(world: &World) -> u64 {
+    world
+        .save()
+        .iter()
+        .fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01B3)
+        })
+}
+
+// Writes down everything that changes a world as it happens
+pub struct Recorder {
+    out: BufWriter<File>,
+    material: CellType,
+    // Simulate calls not written yet, as the tick they started at, steps per call and calls
+    simulating: Option<(u64, u16, u32)>,
+    // Recording stops after the first failed write rather than logging every frame
+    failed: bool,
+}
+impl Recorder {
+    // Restarts the world from a fresh seed and starts recording it
+    pub fn create(
+        path: impl AsRef<Path>,
+        world: &mut World,
+        material: CellType,
+    ) -> io::Result<Self> {
+        let seed = fastrand::u64(..);
+        start(world, seed);
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(
+            out,
+            "{HEADER}\nseed {seed}\nsize {} {}",
+            world.width, world.height
+        )?;
+        let mut recorder = Self {
+            out,
+            material,
+            simulating: None,
+            failed: false,
+        };
+        recorder.event(world, format_args!("material {}", material.name()));
+        Ok(recorder)
+    }
+    // Call before `World::simulate`
+    pub fn simulate(&mut self, world: &World, steps: u16) {
+        match &mut self.simulating {
+            Some((start, merged_steps, times))
+                if *merged_steps == steps
+                    && *times < MAX_MERGED_SIMULATES
+                    && *start + (steps as u64 * *times as u64) == world.ticks =>
+            {
+                *times += 1;
+            }
+            _ => {
+                self.write_simulating();
+                self.simulating = Some((world.ticks, steps, 1));
+            }
+        }
+    }
+    // Call after placing a stroke
+    pub fn stroke(&mut self, world: &World, stroke: Stroke) {
+        let Stroke {
+            x1,
+            y1,
+            x2,
+            y2,
+            radius,
+            material,
+        } = stroke;
+        let name = material.name();
+        self.event(
+            world,
+            format_args!("stroke {x1} {y1} {x2} {y2} {radius} {name}"),
+        );
+    }
+    // Only written when the selection changed, it decides what some actions do
+    pub fn select(&mut self, world: &World, material: CellType) {
+        if material != self.material {
+            self.material = material;
+            self.event(world, format_args!("material {}", material.name()));
+        }
+    }
+    // Call after `World::resize`
+    pub fn resize(&mut self, world: &World) {
+        let (width, height) = (world.width, world.height);
+        self.event(world, format_args!("resize {width} {height}"));
+    }
+    // Call after `Action::apply` or clearing
+    pub fn action(&mut self, world: &World, action: Action) {
+        self.event(world, format_args!("action {action}"));
+    }
+    // Call after the whole grid was swapped out, like by loading or undoing
+    pub fn grid(&mut self, world: &World) {
+        let seed = fastrand::u64(..);
+        fastrand::seed(seed);
+        let hex: String = world
+            .save()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        self.event(world, format_args!("grid {seed} {hex}"));
+    }
+    pub fn finish(mut self, world: &World) {
+        self.event(world, format_args!("end {:016x}", checksum(world)));
+        if let Err(e) = self.out.flush() {
+            log::warn!("Failed to finish recording: {e}");
+        }
+    }
+    fn event(&mut self, world: &World, event: std::fmt::Arguments) {
+        self.write_simulating();
+        self.write(format_args!("{} {event}", world.ticks));
+    }
+    fn write_simulating(&mut self) {
+        if let Some((start, steps, times)) = self.simulating.take() {
+            self.write(format_args!("{start} simulate {steps} {times}"));
+        }
+    }
+    fn write(&mut self, line: std::fmt::Arguments) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = writeln!(self.out, "{line}") {
+            log::warn!("Stopped recording: {e}");
+            self.failed = true;
+        }
+    }
+}
+
+// How a replay compared to the world that was recorded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Matches,
+    Differs,
+    // The recording was cut off before its end was written
+    Unfinished,
+}
+
+// Plays a recording back without a window
+pub fn replay(text: &str) -> Result<(World, Outcome), String> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line));
+    let mut header = |name: &str| {
+        let (number, line) = lines.next().ok_or("Recording is missing its header")?;
+        line.strip_prefix(name)
+            .map(str::trim)
+            .ok_or(format!("Line {number}: expected `{name}`"))
+    };
+    if !header(HEADER)?.is_empty() {
+        return Err(String::from("Not a recording this version can play"));
+    }
+    let seed = header("seed")?;
+    let seed: u64 = seed.parse().map_err(|_| format!("Bad seed {seed}"))?;
+    let size = header("size")?;
+    let (width, height) = size
+        .split_once(' ')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|&(width, height)| World::valid_size(width, height))
+        .ok_or(format!("Bad size {size}"))?;
+
+    let mut world = World::new(width as i32, height as i32, 1);
+    start(&mut world, seed);
+    let mut pixels = vec![0; world.width * world.height * 4];
+    let mut material = CellType::Sand;
+    for (number, line) in lines {
+        let fail = |problem: &str| format!("Line {number}: {problem}");
+        let mut words = line.split_whitespace();
+        let Some(tick) = words.next() else {
+            continue;
+        };
+        if tick.parse() != Ok(world.ticks) {
+            return Err(fail(&format!("replay is at tick {}", world.ticks)));
+        }
+        let kind = words.next().ok_or_else(|| fail("missing event"))?;
+        let arguments: Vec<&str> = words.collect();
+        let number = |index: usize| -> Result<i32, String> {
+            arguments
+                .get(index)
+                .and_then(|word| word.parse().ok())
+                .ok_or_else(|| fail("expected a number"))
+        };
+        match kind {
+            "simulate" => {
+                let (steps, times) = (number(0)?, number(1)?);
+                if !(0..=MAX_SIMULATE_STEPS as i32).contains(&steps)
+                    || !(0..=MAX_MERGED_SIMULATES as i32).contains(&times)
+                {
+                    return Err(fail("too many steps"));
+                }
+                for _ in 0..times {
+                    world.simulate(steps as u16, &mut pixels);
+                }
+            }
+            "stroke" => {
+                let name = arguments.get(5).ok_or_else(|| fail("missing material"))?;
+                let material = CellType::from_name(name).ok_or_else(|| fail("unknown material"))?;
+                // The game records strokes already clamped, but a hand edited or corrupt file
+                // could hold any numbers, and a huge brush or far off point would stall drawing
+                let stroke = Stroke {
+                    x1: number(0)?,
+                    y1: number(1)?,
+                    x2: number(2)?,
+                    y2: number(3)?,
+                    radius: number(4)?,
+                    material,
+                }
+                .clamped(world.width, world.height);
+                world.draw_thick_line(
+                    stroke.x1,
+                    stroke.y1,
+                    stroke.x2,
+                    stroke.y2,
+                    stroke.radius as isize,
+                    stroke.material,
+                    true,
+                    false,
+                    &mut pixels,
+                );
+            }
+            "material" => {
+                let name = arguments.first().ok_or_else(|| fail("missing material"))?;
+                material = CellType::from_name(name).ok_or_else(|| fail("unknown material"))?;
+            }
+            "resize" => {
+                let (width, height) = (number(0)?, number(1)?);
+                let (width, height) = (width.max(0) as usize, height.max(0) as usize);
+                if !World::valid_size(width, height) {
+                    return Err(fail("bad size"));
+                }
+                world.resize(width, height, CenterLocation::new());
+                pixels = vec![0; width * height * 4];
+            }
+            "action" => {
+                let name = arguments.first().ok_or_else(|| fail("missing action"))?;
+                match Action::parse(name, arguments.get(1).copied()) {
+                    Some(Action::Clear) => world.clear(),
+                    Some(action) if action.apply(&mut world, material) => {}
+                    _ => return Err(fail(&format!("can't replay {}", arguments.join(" ")))),
+                }
+            }
+            "grid" => {
+                let seed = arguments.first().and_then(|seed| seed.parse().ok());
+                let seed = seed.ok_or_else(|| fail("expected a seed"))?;
+                let hex = arguments.get(1).ok_or_else(|| fail("missing grid"))?;
+                let bytes = (0..hex.len())
+                    .step_by(2)
+                    .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(|| fail("bad grid"))?;
+                world.load(&bytes).map_err(|e| fail(&e.to_string()))?;
+                fastrand::seed(seed);
+            }
+            "end" => {
+                let expected = arguments.first().ok_or_else(|| fail("missing checksum"))?;
+                let outcome = match format!("{:016x}", checksum(&world)) == *expected {
+                    true => Outcome::Matches,
+                    false => Outcome::Differs,
+                };
+                return Ok((world, outcome));
+            }
+            _ => return Err(fail(&format!("unknown event {kind}"))),
+        }
+    }
+    Ok((world, Outcome::Unfinished))
+}
@@ -1,20 +1,37 @@
 use pixels::{PixelsBuilder, SurfaceTexture};
-use std::rc::Rc;
+use std::{cell::RefCell, rc::Rc};
 use winit::{
-    dpi::LogicalSize,
-    event::{Event, MouseButton, WindowEvent},
+    dpi::{LogicalSize, PhysicalSize},
+    event::{Event, WindowEvent},
     event_loop::EventLoop,
     window::WindowBuilder,
 };
 
-use crate::simulate::world::World;
+use crate::{
+    net::{client::Client, protocol::Stroke},
+    simulate::world::World,
+};
+
+use super::{
+    history::History,
+    input::{Action, InputHelper, Keymap},
+    inspector::Inspector,
+    recording::Recorder,
+};
 
-use super::input::InputHelper;
+const SAVE_PATH: &str = "snad_save.bin";
 
-pub fn run() {
+// With a client the world follows a server, and strokes are sent there instead of placed.
+// With a recording path the session is written there as it is played, see `recording`
+pub fn run(mut client: Option<Client>, recording: Option<String>) {
     // The cellular automata grid
     let cell_count = LogicalSize::new(600, 400);
-    let mut enviornment = World::new(cell_count.width, cell_count.height, 12);
+    let world = World::new(cell_count.width, cell_count.height, 12);
+    // Shared so the web page can read it between frames
+    let world = Rc::new(RefCell::new(world));
+    #[cfg(target_arch = "wasm32")]
+    super::web::share(Rc::clone(&world));
+    let mut enviornment = world.borrow_mut();
     // Event handlers
     let event_loop = EventLoop::new();
 
@@ -26,7 +43,19 @@ pub fn run() {
         .build(&event_loop)
         .expect("Could not instantiate window");
 
-    let mut controller = InputHelper::new(enviornment.density, &window);
+    let (keymap, problems) = Keymap::load("assets/keymap.txt");
+    for problem in problems {
+        log::warn!("[keymap] {problem}");
+    }
+    let mut controller = InputHelper::new(enviornment.density, &window, keymap);
+    let mut history = History::new(16);
+    let mut inspector = Inspector::new();
+    let mut title = String::new();
+    let mut recorder = recording.and_then(|path| {
+        Recorder::create(&path, &mut enviornment, controller.material)
+            .map_err(|e| log::warn!("Could not record to {path}: {e}"))
+            .ok()
+    });
 
     let window = Rc::new(window);
 
@@ -84,27 +113,40 @@ pub fn run() {
         .expect("Could not instantiate Pixels")
     };
     enviornment.render(pixels.frame_mut());
+    drop(enviornment);
 
     // Run Every frame
     event_loop.run(move |event, _, control_flow| {
+        let mut enviornment = world.borrow_mut();
         // println!("{event:?}");
+        enviornment.debug_view = controller.debug_view;
         enviornment.render(pixels.frame_mut());
 
         control_flow.set_poll();
         controller.hook_events(&event);
+        inspector.hook_events(&event);
         match event {
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
             } => {
+                if let Some(recorder) = recorder.take() {
+                    recorder.finish(&enviornment);
+                }
                 control_flow.set_exit();
             }
             Event::WindowEvent {
                 event: WindowEvent::Resized(size),
                 ..
             } => {
-                let width = size.width / enviornment.density;
-                let height = size.height / enviornment.density;
+                // A server's world keeps its size, whatever the window does
+                let (width, height) = match client {
+                    Some(_) => (enviornment.width as u32, enviornment.height as u32),
+                    None => (
+                        size.width / enviornment.density,
+                        size.height / enviornment.density,
+                    ),
+                };
                 pixels
                     .resize_surface(size.width, size.height)
                     .expect("Failed to resize surface");
@@ -112,33 +154,189 @@ pub fn run() {
                     .resize_buffer(width, height)
                     .expect("Failed to resize buffer");
                 let (resize, expand) = controller.resized();
-                if let Some(e) = expand {
+                if let (Some(e), None) = (expand, &client) {
                     println!("{width:?} {height:?}");
+                    let density = enviornment.density;
                     enviornment.resize(
-                        (resize.width / enviornment.density) as usize,
-                        (resize.height / enviornment.density) as usize,
+                        (resize.width / density) as usize,
+                        (resize.height / density) as usize,
                         e,
                     );
+                    if let Some(recorder) = &mut recorder {
+                        recorder.resize(&enviornment);
+                    }
                 }
                 enviornment.render(pixels.frame_mut());
             }
             Event::MainEventsCleared => {
-                for i in 0..1 {
-                    enviornment.simulate(2, pixels.frame_mut());
+                #[cfg(target_arch = "wasm32")]
+                for command in super::web::take_commands() {
+                    use super::web::Command;
+                    match command {
+                        Command::SelectMaterial(material) => controller.material = material,
+                        Command::BrushSize(size) => controller.set_selection_size(size),
+                        Command::Pause(paused) => controller.paused = paused,
+                        // A server's world is only changed through strokes
+                        Command::Clear | Command::Load(_) if client.is_some() => {}
+                        Command::Clear => {
+                            history.push(&enviornment);
+                            enviornment.clear();
+                        }
+                        Command::Load(bytes) => {
+                            history.push(&enviornment);
+                            if let Err(e) = enviornment.load(&bytes) {
+                                log::warn!("Failed to load: {e}");
+                            }
+                        }
+                    }
+                }
+                let paused = controller.paused;
+                // Cells can only be edited in a world that is paused and not following a server
+                let editable = paused && client.is_none();
+                // Some actions use the selected material, so it has to be written before them
+                if let Some(recorder) = &mut recorder {
+                    recorder.select(&enviornment, controller.material);
+                }
+                for action in controller.take_actions() {
+                    // Clients follow the server's world, changing it here would put it out of step
+                    if client.is_some() && action.changes_world() {
+                        continue;
+                    }
+                    match action {
+                        Action::Step if paused => {
+                            if let Some(recorder) = &mut recorder {
+                                recorder.simulate(&enviornment, 1);
+                            }
+                            enviornment.simulate(1, pixels.frame_mut());
+                        }
+                        Action::Save => {
+                            if let Err(e) = std::fs::write(SAVE_PATH, enviornment.save()) {
+                                log::warn!("Failed to save: {e}");
+                            }
+                        }
+                        Action::Load => {
+                            history.push(&enviornment);
+                            let loaded = std::fs::read(SAVE_PATH)
+                                .map_err(|e| e.to_string())
+                                .and_then(|bytes| {
+                                    enviornment.load(&bytes).map_err(|e| e.to_string())
+                                });
+                            if let Err(e) = loaded {
+                                log::warn!("Failed to load: {e}");
+                            }
+                            if let Some(recorder) = &mut recorder {
+                                recorder.grid(&enviornment);
+                            }
+                        }
+                        Action::Undo => {
+                            history.undo(&mut enviornment);
+                            if let Some(recorder) = &mut recorder {
+                                recorder.grid(&enviornment);
+                            }
+                        }
+                        Action::Clear => {
+                            history.push(&enviornment);
+                            enviornment.clear();
+                            if let Some(recorder) = &mut recorder {
+                                recorder.action(&enviornment, action);
+                            }
+                        }
+                        _ if action.apply(&mut enviornment, controller.material) => {
+                            if let Some(recorder) = &mut recorder {
+                                recorder.action(&enviornment, action);
+                            }
+                        }
+                        _ => inspector.perform(action, editable),
+                    }
+                }
+                match &mut client {
+                    // The server does the simulating, pausing only stops the local copy
+                    Some(client) => {
+                        client.poll(&mut enviornment);
+                        let size =
+                            PhysicalSize::new(enviornment.width as u32, enviornment.height as u32);
+                        let texture = pixels.texture();
+                        if (texture.width(), texture.height()) != size.into() {
+                            pixels
+                                .resize_buffer(size.width, size.height)
+                                .expect("Failed to resize buffer");
+                            controller.fix_grid_size(size);
+                            enviornment.render(pixels.frame_mut());
+                        }
+                    }
+                    None if !paused => {
+                        if let Some(recorder) = &mut recorder {
+                            recorder.simulate(&enviornment, 2);
+                        }
+                        enviornment.simulate(2, pixels.frame_mut());
+                    }
+                    None => {}
+                }
+                let inspected = inspector.update(&mut enviornment, &mut controller, editable);
+                let mut new_title = match inspected {
+                    Some(text) => format!("Snad Stack - {text}"),
+                    None => String::from("Snad Stack"),
+                };
+                if client.as_ref().is_some_and(|client| !client.connected()) {
+                    new_title.push_str(" (reconnecting)");
+                }
+                if new_title != title {
+                    window.set_title(&new_title);
+                    title = new_title;
+                }
+                controller.eyedropper(&enviornment);
+                let brush = controller.brush_material().filter(|_| !inspector.enabled);
+                history.track_stroke(&enviornment, brush.is_some());
+                // Picking a material off the grid changes the selection too
+                if let Some(recorder) = &mut recorder {
+                    recorder.select(&enviornment, controller.material);
                 }
                 if let Some((current, previous)) = controller.pixel_position(&enviornment) {
-                    enviornment.draw_thick_line(
-                        current.x,
-                        current.y,
-                        previous.x,
-                        previous.y,
-                        controller.selection_size(),
-                        controller.material,
-                        controller.mouse_clicked(MouseButton::Left),
-                        true,
-                        pixels.frame_mut(),
-                    );
+                    // Clamped like a server or a replay will, so they place exactly the same cells
+                    let stroke = brush.map(|material| {
+                        Stroke {
+                            x1: current.x,
+                            y1: current.y,
+                            x2: previous.x,
+                            y2: previous.y,
+                            radius: controller.selection_size() as i32,
+                            material,
+                        }
+                        .clamped(enviornment.width, enviornment.height)
+                    });
+                    if let (Some(client), Some(stroke)) = (&mut client, stroke) {
+                        client.send_stroke(stroke);
+                    }
+                    match stroke.filter(|_| client.is_none()) {
+                        Some(stroke) => enviornment.draw_thick_line(
+                            stroke.x1,
+                            stroke.y1,
+                            stroke.x2,
+                            stroke.y2,
+                            stroke.radius as isize,
+                            stroke.material,
+                            true,
+                            true,
+                            pixels.frame_mut(),
+                        ),
+                        None => enviornment.draw_thick_line(
+                            current.x,
+                            current.y,
+                            previous.x,
+                            previous.y,
+                            controller.selection_size(),
+                            controller.material,
+                            false,
+                            true,
+                            pixels.frame_mut(),
+                        ),
+                    }
+                    if let (Some(recorder), Some(stroke)) = (&mut recorder, stroke) {
+                        recorder.stroke(&enviornment, stroke);
+                    }
                 }
+                #[cfg(target_arch = "wasm32")]
+                super::web::publish(&mut controller);
                 window.request_redraw();
             }
 
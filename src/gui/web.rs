@@ -0,0 +1,136 @@
+use std::{cell::RefCell, rc::Rc};
+
+use wasm_bindgen::prelude::*;
+
+use crate::simulate::{cells::CellType, save, world::World};
+
+use super::input::{InputHelper, MAX_BRUSH_SIZE};
+
+// What the page asks for that has to go through the game loop, applied on the next frame
+pub enum Command {
+    SelectMaterial(CellType),
+    BrushSize(f32),
+    Pause(bool),
+    Clear,
+    Load(Vec<u8>),
+}
+
+// The controller state last seen by the game loop, for the page to read back
+#[derive(Clone, Copy)]
+struct Controls {
+    material: CellType,
+    brush_size: f32,
+    max_brush_size: f32,
+    paused: bool,
+}
+
+thread_local! {
+    static WORLD: RefCell<Option<Rc<RefCell<World>>>> = const { RefCell::new(None) };
+    static COMMANDS: RefCell<Vec<Command>> = const { RefCell::new(Vec::new()) };
+    static CONTROLS: RefCell<Controls> = const {
+        RefCell::new(Controls {
+            material: CellType::Sand,
+            brush_size: 1.0,
+            max_brush_size: MAX_BRUSH_SIZE,
+            paused: false,
+        })
+    };
+}
+
+// Lets the page read the world between frames
+pub fn share(world: Rc<RefCell<World>>) {
+    WORLD.with(|shared| *shared.borrow_mut() = Some(world));
+}
+pub fn take_commands() -> Vec<Command> {
+    COMMANDS.with(|commands| std::mem::take(&mut *commands.borrow_mut()))
+}
+pub fn publish(controller: &mut InputHelper) {
+    let controls = Controls {
+        material: controller.material,
+        // What was chosen, pen pressure would make it jump around under the slider
+        brush_size: controller.chosen_selection_size(),
+        max_brush_size: controller.max_selection_size(),
+        paused: controller.paused,
+    };
+    CONTROLS.with(|shared| *shared.borrow_mut() = controls);
+}
+
+fn push(command: Command) {
+    COMMANDS.with(|commands| commands.borrow_mut().push(command));
+}
+fn with_world<T>(f: impl FnOnce(&World) -> T) -> Result<T, JsError> {
+    WORLD.with(|shared| match &*shared.borrow() {
+        Some(world) => Ok(f(&world.borrow())),
+        None => Err(JsError::new("The game has not started yet")),
+    })
+}
+
+// Selects a material by name, false if there is no such material
+#[wasm_bindgen]
+pub fn set_material(name: &str) -> bool {
+    let material = CellType::from_name(name);
+    if let Some(material) = material {
+        push(Command::SelectMaterial(material));
+    }
+    material.is_some()
+}
+#[wasm_bindgen]
+pub fn material() -> String {
+    CONTROLS.with(|controls| controls.borrow().material.name().to_string())
+}
+// Name of the material with an id, ids index `material_counts`
+#[wasm_bindgen]
+pub fn material_name(id: usize) -> Option<String> {
+    CellType::ALL
+        .get(id)
+        .map(|material| material.name().to_string())
+}
+#[wasm_bindgen]
+pub fn set_brush_size(size: f32) {
+    push(Command::BrushSize(size));
+}
+#[wasm_bindgen]
+pub fn brush_size() -> f32 {
+    CONTROLS.with(|controls| controls.borrow().brush_size)
+}
+// Brush sizes past this are clamped to it
+#[wasm_bindgen]
+pub fn max_brush_size() -> f32 {
+    CONTROLS.with(|controls| controls.borrow().max_brush_size)
+}
+#[wasm_bindgen]
+pub fn set_paused(paused: bool) {
+    push(Command::Pause(paused));
+}
+#[wasm_bindgen]
+pub fn paused() -> bool {
+    CONTROLS.with(|controls| controls.borrow().paused)
+}
+// Clearing can be undone like it can from the keyboard
+#[wasm_bindgen]
+pub fn clear() {
+    push(Command::Clear);
+}
+// The same bytes the native game saves to a file
+#[wasm_bindgen]
+pub fn save() -> Result<Vec<u8>, JsError> {
+    with_world(World::save)
+}
+// Only the header is checked here, anything wrong past it is logged when the save is loaded
+#[wasm_bindgen]
+pub fn load(bytes: &[u8]) -> Result<(), JsError> {
+    save::read_header(bytes).map_err(|e| JsError::new(&e.to_string()))?;
+    push(Command::Load(bytes.to_vec()));
+    Ok(())
+}
+// How many cells of each material there are, indexed by material id
+#[wasm_bindgen]
+pub fn material_counts() -> Result<Vec<u32>, JsError> {
+    with_world(|world| {
+        let mut counts = vec![0; CellType::ALL.len()];
+        for material in &world.grid.materials {
+            counts[*material as usize] += 1;
+        }
+        counts
+    })
+}
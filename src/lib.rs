@@ -1,4 +1,5 @@
 #![allow(clippy::new_without_default)]
 
 pub mod gui;
+pub mod net;
 pub mod simulate;
@@ -1,19 +1,142 @@
+#![allow(clippy::new_without_default)]
+
 pub mod gui;
+pub mod net;
 pub mod simulate;
 
+use gui::recording::Outcome;
+use net::{client::Client, server::Server};
+use simulate::world::World;
+
+const USAGE: &str = "Usage: snad_stack [--record <file>]
+       snad_stack --replay <file> [--out <save file>]
+       snad_stack --connect <address>
+       snad_stack --serve <address> [--size <width>x<height>]
+  --record   write everything done in the session to a file that can be replayed
+  --replay   play a recording back without a window and check it ends the same way
+  --out      where to save the world a replay ends with
+  --connect  play in the world of a server instead of a local one
+  --serve    run a server without a window, other players --connect to it
+  --size     size of the served world in cells, 400x300 by default";
+
+enum Mode {
+    Local(Option<String>),
+    Replay(String, Option<String>),
+    Connect(String),
+    Serve(String, usize, usize),
+}
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Mode, String> {
+    let (mut connect, mut serve, mut size) = (None, None, (400, 300));
+    let (mut record, mut replay, mut out) = (None, None, None);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--connect" => connect = Some(value()?),
+            "--serve" => serve = Some(value()?),
+            "--record" => record = Some(value()?),
+            "--replay" => replay = Some(value()?),
+            "--out" => out = Some(value()?),
+            "--size" => {
+                let text = value()?;
+                size = text
+                    .split_once('x')
+                    .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                    .filter(|(width, height)| World::valid_size(*width, *height))
+                    .ok_or(format!("{text} is not a size like 400x300"))?;
+            }
+            _ => return Err(format!("Unknown argument {arg}")),
+        }
+    }
+    let modes = [
+        connect.is_some(),
+        serve.is_some(),
+        record.is_some(),
+        replay.is_some(),
+    ];
+    if modes.into_iter().filter(|mode| *mode).count() > 1 {
+        return Err(String::from(
+            "Only one of --connect, --serve, --record and --replay at a time",
+        ));
+    }
+    if out.is_some() && replay.is_none() {
+        return Err(String::from("--out only goes with --replay"));
+    }
+    match (connect, serve, replay) {
+        (Some(address), _, _) => Ok(Mode::Connect(address)),
+        (_, Some(address), _) => Ok(Mode::Serve(address, size.0, size.1)),
+        (_, _, Some(path)) => Ok(Mode::Replay(path, out)),
+        _ => Ok(Mode::Local(record)),
+    }
+}
+
 fn main() {
     #[cfg(target_arch = "wasm32")]
     {
         std::panic::set_hook(Box::new(console_error_panic_hook::hook));
         console_log::init_with_level(log::Level::Info).expect("Failed setting logger");
-        wasm_bindgen_futures::spawn_local(run());
+        wasm_bindgen_futures::spawn_local(run(None, None));
     }
 
     #[cfg(not(target_arch = "wasm32"))]
     {
-        pollster::block_on(run());
+        let mode = parse_args(std::env::args().skip(1)).unwrap_or_else(|problem| {
+            eprintln!("{problem}\n{USAGE}");
+            std::process::exit(2)
+        });
+        let (client, recording) = match mode {
+            Mode::Local(recording) => (None, recording),
+            Mode::Replay(path, out) => {
+                replay(&path, out.as_deref());
+                return;
+            }
+            Mode::Connect(address) => match Client::connect(&address) {
+                Ok(client) => (Some(client), None),
+                Err(e) => {
+                    eprintln!("Could not connect to {address}: {e}");
+                    std::process::exit(1)
+                }
+            },
+            Mode::Serve(address, width, height) => {
+                let mut server = Server::bind(&address, width, height).unwrap_or_else(|e| {
+                    eprintln!("Could not serve on {address}: {e}");
+                    std::process::exit(1)
+                });
+                println!("Serving a {width}x{height} world on {address}");
+                server.run();
+                return;
+            }
+        };
+        pollster::block_on(run(client, recording));
     }
 }
-async fn run() {
-    gui::run::run();
+async fn run(client: Option<Client>, recording: Option<String>) {
+    gui::run::run(client, recording);
+}
+#[cfg(not(target_arch = "wasm32"))]
+fn replay(path: &str, out: Option<&str>) {
+    let replayed = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| gui::recording::replay(&text));
+    let (world, outcome) = replayed.unwrap_or_else(|problem| {
+        eprintln!("Could not replay {path}: {problem}");
+        std::process::exit(1)
+    });
+    println!(
+        "Replayed {} ticks of a {}x{} world",
+        world.ticks, world.width, world.height
+    );
+    if let Some(out) = out {
+        if let Err(e) = std::fs::write(out, world.save()) {
+            eprintln!("Could not save to {out}: {e}");
+            std::process::exit(1)
+        }
+    }
+    match outcome {
+        Outcome::Matches => println!("The final grid matches the recording"),
+        Outcome::Unfinished => println!("The recording was cut off, there is nothing to compare"),
+        Outcome::Differs => {
+            eprintln!("The final grid differs from the recording");
+            std::process::exit(1)
+        }
+    }
 }
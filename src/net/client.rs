@@ -0,0 +1,104 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::simulate::world::World;
+
+use super::protocol::{Message, Stroke};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// How long to wait between attempts to get back to a server that went away
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Connection {
+    stream: TcpStream,
+    // Messages are read on their own thread so polling never blocks
+    messages: Receiver<Message>,
+}
+impl Connection {
+    fn open(address: SocketAddr) -> io::Result<Self> {
+        let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+        let mut reader = stream.try_clone()?;
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(message) = Message::read(&mut reader) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self { stream, messages })
+    }
+}
+
+// Follows a server's world. Strokes are forwarded to the server, and the world only changes by
+// what the server sends back
+pub struct Client {
+    address: SocketAddr,
+    connection: Option<Connection>,
+    last_attempt: Instant,
+}
+impl Client {
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let address = address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "No address to connect to")
+        })?;
+        Ok(Self {
+            address,
+            connection: Some(Connection::open(address)?),
+            last_attempt: Instant::now(),
+        })
+    }
+    pub fn connected(&self) -> bool {
+        self.connection.is_some()
+    }
+    // Strokes made while disconnected are dropped
+    pub fn send_stroke(&mut self, stroke: Stroke) {
+        let Some(connection) = &mut self.connection else {
+            return;
+        };
+        if let Err(e) = Message::Stroke(stroke).write(&mut connection.stream) {
+            log::warn!("Lost connection to {}: {e}", self.address);
+            self.connection = None;
+        }
+    }
+    // Applies everything the server has sent so far, and tries to reconnect if it went away
+    pub fn poll(&mut self, world: &mut World) {
+        if self.connection.is_none() && self.last_attempt.elapsed() >= RECONNECT_INTERVAL {
+            self.last_attempt = Instant::now();
+            match Connection::open(self.address) {
+                Ok(connection) => {
+                    log::info!("Reconnected to {}", self.address);
+                    self.connection = Some(connection);
+                }
+                Err(e) => log::info!("Could not reconnect to {}: {e}", self.address),
+            }
+        }
+        let Some(connection) = &self.connection else {
+            return;
+        };
+        loop {
+            let applied = match connection.messages.try_recv() {
+                // The server decides how big the world is
+                Ok(Message::Keyframe(save)) => world.load_resized(&save),
+                Ok(Message::Diff(diff)) => world.load_diff(&diff),
+                Ok(Message::Stroke(_)) => Ok(()),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    log::warn!("Lost connection to {}", self.address);
+                    self.connection = None;
+                    self.last_attempt = Instant::now();
+                    break;
+                }
+            };
+            if let Err(e) = applied {
+                log::warn!("Bad update from {}: {e}", self.address);
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::io::{self, Read, Write};
+
+use miniz_oxide::{deflate::compress_to_vec, inflate::decompress_to_vec_with_limit};
+
+use crate::{gui::input::MAX_BRUSH_SIZE, simulate::cells::CellType};
+
+// Every message is framed as a kind byte and a little endian payload length, then the payload
+const STROKE: u8 = 0;
+const KEYFRAME: u8 = 1;
+const DIFF: u8 = 2;
+const FRAME_HEADER_SIZE: usize = 5;
+const STROKE_SIZE: usize = 21;
+// Anything longer is corrupt, and would otherwise be allocated before finding that out. Also the
+// most a compressed payload may inflate to
+const MAX_PAYLOAD: usize = 256 << 20;
+// Fastest deflate level, a frame has to be compressed 30 times a second
+const COMPRESSION_LEVEL: u8 = 1;
+
+// The arguments of a placing `World::draw_thick_line` call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stroke {
+    pub x1: i32,
+    pub y1: i32,
+    pub x2: i32,
+    pub y2: i32,
+    pub radius: i32,
+    pub material: CellType,
+}
+
+impl Stroke {
+    // Keeps a stroke inside the world, with a brush no bigger than the local one can be. Strokes
+    // from clients and recordings can't be trusted, a huge brush would stall drawing and far off
+    // points overflow
+    pub fn clamped(self, width: usize, height: usize) -> Self {
+        let (max_x, max_y) = (
+            width.saturating_sub(1) as i32,
+            height.saturating_sub(1) as i32,
+        );
+        Self {
+            x1: self.x1.clamp(0, max_x),
+            y1: self.y1.clamp(0, max_y),
+            x2: self.x2.clamp(0, max_x),
+            y2: self.y2.clamp(0, max_y),
+            radius: self.radius.clamp(1, MAX_BRUSH_SIZE as i32),
+            material: self.material,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Message {
+    // Client to server, paint a line
+    Stroke(Stroke),
+    // Server to client, a full `World::save`. Deflated on the way
+    Keyframe(Vec<u8>),
+    // Server to client, a `World::diff` against the last keyframe or diff. Deflated on the way
+    Diff(Vec<u8>),
+}
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let (kind, payload) = match self {
+            Message::Stroke(stroke) => {
+                let mut payload = Vec::with_capacity(STROKE_SIZE);
+                for value in [stroke.x1, stroke.y1, stroke.x2, stroke.y2, stroke.radius] {
+                    payload.extend_from_slice(&value.to_le_bytes());
+                }
+                payload.push(stroke.material as u8);
+                (STROKE, payload)
+            }
+            // Neighbouring cells are mostly the same material, so saves shrink a lot
+            Message::Keyframe(save) => (KEYFRAME, compress_to_vec(save, COMPRESSION_LEVEL)),
+            Message::Diff(diff) => (DIFF, compress_to_vec(diff, COMPRESSION_LEVEL)),
+        };
+        let mut out = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+        out.push(kind);
+        out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        out.extend_from_slice(&payload);
+        out
+    }
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        // One write per message, so small strokes aren't split up on the way
+        writer.write_all(&self.encode())
+    }
+    // Blocks until a whole message has arrived
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let mut header = [0; FRAME_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        let length = u32::from_le_bytes(header[1..5].try_into().unwrap()) as usize;
+        if length > MAX_PAYLOAD {
+            return Err(invalid(format!("Message of {length} bytes is too long")));
+        }
+        let mut payload = vec![0; length];
+        reader.read_exact(&mut payload)?;
+        match header[0] {
+            STROKE if length == STROKE_SIZE => decode_stroke(&payload).map(Message::Stroke),
+            KEYFRAME => inflate(&payload).map(Message::Keyframe),
+            DIFF => inflate(&payload).map(Message::Diff),
+            kind => Err(invalid(format!("Unknown message kind {kind}"))),
+        }
+    }
+    // Blocks until a whole stroke has arrived. Strokes are all clients send, so anything else is
+    // refused before its payload is read
+    pub fn read_stroke(reader: &mut impl Read) -> io::Result<Stroke> {
+        let mut header = [0; FRAME_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        let length = u32::from_le_bytes(header[1..5].try_into().unwrap()) as usize;
+        if header[0] != STROKE || length != STROKE_SIZE {
+            return Err(invalid(format!(
+                "Expected a stroke, got kind {} of {length} bytes",
+                header[0]
+            )));
+        }
+        let mut payload = [0; STROKE_SIZE];
+        reader.read_exact(&mut payload)?;
+        decode_stroke(&payload)
+    }
+}
+
+fn decode_stroke(payload: &[u8]) -> io::Result<Stroke> {
+    let value =
+        |index: usize| i32::from_le_bytes(payload[index * 4..index * 4 + 4].try_into().unwrap());
+    // None is only a placeholder, not something that can be drawn
+    let material = *CellType::ALL
+        .get(payload[20] as usize)
+        .filter(|material| **material != CellType::None)
+        .ok_or_else(|| invalid(format!("Unknown material id {}", payload[20])))?;
+    Ok(Stroke {
+        x1: value(0),
+        y1: value(1),
+        x2: value(2),
+        y2: value(3),
+        radius: value(4),
+        material,
+    })
+}
+
+fn inflate(payload: &[u8]) -> io::Result<Vec<u8>> {
+    decompress_to_vec_with_limit(payload, MAX_PAYLOAD)
+        .map_err(|e| invalid(format!("Could not inflate message: {e}")))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
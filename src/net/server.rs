@@ -0,0 +1,192 @@
+use std::{
+    io::{self, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{self, Receiver, Sender, SyncSender, TrySendError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::simulate::{grid::Grid, world::World};
+
+use super::protocol::{Message, Stroke};
+
+// Frames simulated per second, each steps the world twice like the local game does every frame
+pub const FRAME_RATE: u64 = 30;
+// Clients are sent a keyframe this often even when diffs would be smaller, which also undoes
+// anything they changed on their own copy
+const KEYFRAME_INTERVAL: u64 = 10 * FRAME_RATE;
+// Frames queued for a client that isn't keeping up, once a second's worth are waiting it is
+// dropped rather than holding everyone up
+const QUEUED_FRAMES: usize = FRAME_RATE as usize;
+
+struct Connection {
+    address: SocketAddr,
+    stream: TcpStream,
+    // Each client is written to on its own thread, so the frame loop never waits on the network
+    frames: SyncSender<Arc<[u8]>>,
+}
+
+// Owns the one true world. Clients send it strokes, and every frame are sent back what changed
+pub struct Server {
+    pub world: World,
+    listener: TcpListener,
+    clients: Vec<Connection>,
+    // Clients each read on their own thread and pass strokes along here
+    stroke_sender: Sender<Stroke>,
+    strokes: Receiver<Stroke>,
+    // The grid every client has been brought up to, diffs are taken against it
+    sent: Grid,
+    pixels: Vec<u8>,
+    frames: u64,
+}
+impl Server {
+    pub fn bind(address: impl ToSocketAddrs, width: usize, height: usize) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let world = World::new(width as i32, height as i32, 1);
+        let (stroke_sender, strokes) = mpsc::channel();
+        Ok(Self {
+            sent: world.grid.clone(),
+            pixels: vec![0; width * height * 4],
+            world,
+            listener,
+            clients: vec![],
+            stroke_sender,
+            strokes,
+            frames: 0,
+        })
+    }
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+    // Applies strokes, simulates and sends out the changes, then lets in anyone waiting to join
+    pub fn frame(&mut self) {
+        while let Ok(stroke) = self.strokes.try_recv() {
+            let stroke = stroke.clamped(self.world.width, self.world.height);
+            self.world.draw_thick_line(
+                stroke.x1,
+                stroke.y1,
+                stroke.x2,
+                stroke.y2,
+                stroke.radius as isize,
+                stroke.material,
+                true,
+                false,
+                &mut self.pixels,
+            );
+        }
+        self.world.simulate(2, &mut self.pixels);
+        self.frames += 1;
+
+        let message = match self.world.diff(&self.sent) {
+            Some(diff)
+                if !self.frames.is_multiple_of(KEYFRAME_INTERVAL)
+                    && diff.len() < self.world.save_size() =>
+            {
+                Message::Diff(diff)
+            }
+            _ => Message::Keyframe(self.world.save()),
+        };
+        self.sent.copy_from(&self.world.grid);
+        let frame: Arc<[u8]> = message.encode().into();
+        self.clients.retain(|client| {
+            let reason = match client.frames.try_send(frame.clone()) {
+                Ok(()) => return true,
+                Err(TrySendError::Full(_)) => "fell behind",
+                Err(TrySendError::Disconnected(_)) => "left",
+            };
+            log::info!("{} {reason}", client.address);
+            let _ = client.stream.shutdown(Shutdown::Both);
+            false
+        });
+        self.accept();
+    }
+    // Runs frames at `FRAME_RATE` forever
+    pub fn run(&mut self) {
+        let frame_time = Duration::from_secs(1) / FRAME_RATE as u32;
+        loop {
+            let start = Instant::now();
+            self.frame();
+            thread::sleep(frame_time.saturating_sub(start.elapsed()));
+        }
+    }
+    fn accept(&mut self) {
+        // Everyone joining at once starts from the same keyframe
+        let mut keyframe = None;
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    let keyframe = keyframe.get_or_insert_with(|| {
+                        // Clients have just been sent everything up to the current world
+                        Arc::<[u8]>::from(Message::Keyframe(self.world.save()).encode())
+                    });
+                    if let Err(e) = self.join(stream, address, keyframe.clone()) {
+                        log::warn!("{address} could not join: {e}");
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("Failed to accept a client: {e}");
+                    break;
+                }
+            }
+        }
+    }
+    // New and returning clients alike start from a keyframe of what everyone else has
+    fn join(
+        &mut self,
+        stream: TcpStream,
+        address: SocketAddr,
+        keyframe: Arc<[u8]>,
+    ) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+
+        let (frames, queued) = mpsc::sync_channel::<Arc<[u8]>>(QUEUED_FRAMES);
+        frames.try_send(keyframe).unwrap();
+        let mut writer = stream.try_clone()?;
+        thread::spawn(move || {
+            for frame in queued {
+                if writer.write_all(&frame).is_err() {
+                    break;
+                }
+            }
+            let _ = writer.shutdown(Shutdown::Both);
+        });
+
+        let mut reader = stream.try_clone()?;
+        let strokes = self.stroke_sender.clone();
+        thread::spawn(move || {
+            // Clients only ever send strokes, anything else ends the connection
+            while let Ok(stroke) = Message::read_stroke(&mut reader) {
+                if strokes.send(stroke).is_err() {
+                    break;
+                }
+            }
+            // Ends the writer thread too, so the server drops the client on its next frame
+            let _ = reader.shutdown(Shutdown::Both);
+        });
+        log::info!("{address} joined");
+        self.clients.push(Connection {
+            address,
+            stream,
+            frames,
+        });
+        Ok(())
+    }
+}
+// Reader threads hold their own handle to each connection, so they have to be shut down for
+// clients to notice the server has gone
+impl Drop for Server {
+    fn drop(&mut self) {
+        for client in &self.clients {
+            let _ = client.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
@@ -1,10 +1,19 @@
 use std::cell::UnsafeCell;
-use std::sync::Barrier;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Barrier, Mutex};
 
-use super::cells::{Cell, CellType};
-use super::logic::simulate_steps;
+use super::boundary::{Boundaries, Boundary};
+use super::cells::{Cell, CellState, CellType};
+use super::gravity::{Gravity, GravityDirection};
+use super::logic::{simulate_deferred, simulate_steps};
+use super::particles::Particle;
 use super::world::World;
 
+// A sparked conductor passes the spark on for SPARK_LENGTH ticks, then rests for REFRACTORY_LENGTH
+// ticks before it can be sparked again
+pub const SPARK_LENGTH: u8 = 3;
+pub const REFRACTORY_LENGTH: u8 = 6;
+
 const ITERATION_ORD_X: &str = match option_env!("SNAD_SIM_ORD_X") {
     Some(e) => e,
     None => "LINEAR",
@@ -13,6 +22,30 @@ const ITERATION_ORD_Y: &str = match option_env!("SNAD_SIM_ORD_Y") {
     Some(e) => e,
     None => "REVERSED",
 };
+// Chance each tick that an empty cell along a source edge is filled
+const SOURCE_CHANCE: u8 = 8;
+
+// Compass directions as offsets, clockwise starting from up
+pub const DIRECTIONS: [(isize, isize); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+
+// Every offset within a circle, the same test `PlaceLineType::Circle` stamps with
+pub fn circle_offsets(radius: isize) -> impl Iterator<Item = (isize, isize)> {
+    (-radius..=radius).flat_map(move |y| {
+        (-radius..=radius)
+            .filter(move |x| x * x + y * y <= radius * radius)
+            .map(move |x| (x, y))
+    })
+}
+
 // Each thread gets it's own copy
 // Change shared grid for performance
 pub struct CellsApi<'a> {
@@ -20,18 +53,41 @@ pub struct CellsApi<'a> {
     pub world: &'a mut World,
     pub main_thread_barrier: &'a Barrier,
     pub synchronize_barrier: &'a Barrier,
-    pub none_cell: Cell,
+    pub finish_barrier: &'a Barrier,
+    pub launched: &'a Mutex<Vec<Particle>>,
+    // Positions put off with `defer`, handed over to the main thread at the end of a step
+    deferred: Vec<(usize, usize)>,
+    shared_deferred: &'a Mutex<Vec<(usize, usize)>>,
+    threaded: bool,
+    // Kept between searches through connected cells so they don't allocate every time
+    pub visited: HashSet<(isize, isize)>,
+    pub queue: VecDeque<(isize, isize)>,
+    // Stands in for the state of cells past the edge of the world
+    pub none_state: CellState,
+    pub gravity: Gravity,
+    pub boundaries: Boundaries,
     pub x: isize,
     pub y: isize,
 }
 impl<'a> CellsApi<'a> {
     pub fn new(shared: &'a mut SharedCellApi<'a>) -> CellsApi<'a> {
+        let gravity = shared.world.gravity;
+        let boundaries = shared.world.boundaries;
         Self {
             pixels: shared.pixels,
             world: shared.world,
             main_thread_barrier: &shared.main_barrier,
             synchronize_barrier: &shared.sync_barrier,
-            none_cell: Cell::new(CellType::None),
+            finish_barrier: &shared.finish_barrier,
+            launched: &shared.launched,
+            deferred: vec![],
+            shared_deferred: &shared.deferred,
+            threaded: shared.threaded,
+            visited: HashSet::new(),
+            queue: VecDeque::new(),
+            none_state: CellState::default(),
+            gravity,
+            boundaries,
             x: 0,
             y: 0,
         }
@@ -42,8 +98,45 @@ impl<'a> CellsApi<'a> {
     pub fn wait_start(&mut self) {
         self.main_thread_barrier.wait();
     }
+    // Every thread is done with the step once this returns
+    pub fn wait_finish(&mut self) {
+        self.shared_deferred
+            .lock()
+            .unwrap()
+            .append(&mut self.deferred);
+        self.finish_barrier.wait();
+    }
+    // Effects that reach further than the cells next to the current one would race other threads
+    // simulating nearby strips. While threaded this queues the current cell to run
+    // `simulate_deferred` once every strip is done and returns true, the effect should wait till then
+    pub fn defer(&mut self) -> bool {
+        if self.threaded {
+            self.deferred.push((self.x as usize, self.y as usize));
+        }
+        self.threaded
+    }
+    // Runs everything that was deferred, only on the main thread after `wait_finish`
+    pub fn run_deferred(&mut self) {
+        let deferred = std::mem::take(&mut *self.shared_deferred.lock().unwrap());
+        self.threaded = false;
+        for (x, y) in deferred {
+            self.set_position(x, y);
+            simulate_deferred(self);
+        }
+        self.threaded = true;
+    }
+    // Moves rigid bodies on by a tick, only on one thread once every strip is done
+    pub fn step_bodies(&mut self) {
+        self.world.step_bodies(self.pixels);
+    }
     #[inline]
-    fn iter_axis(&mut self, iter_type: &'static str, start: usize, length: usize) -> Vec<usize> {
+    fn iter_axis(
+        &mut self,
+        iter_type: &'static str,
+        start: usize,
+        length: usize,
+        flip: bool,
+    ) -> Vec<usize> {
         let base = start..length;
         let mut result: Vec<usize> = if iter_type.starts_with("REVERSED") {
             base.rev().collect()
@@ -55,11 +148,26 @@ impl<'a> CellsApi<'a> {
         if iter_type.ends_with("SHUFFLED") {
             fastrand::shuffle(&mut result)
         }
+        if flip {
+            result.reverse();
+        }
         result
     }
     pub fn simulate(&mut self, x1: usize, x2: usize) {
-        let iter_y = self.iter_axis(ITERATION_ORD_Y, 0, self.world.height);
-        let iter_x = self.iter_axis(ITERATION_ORD_X, x1, x2);
+        // Sweep against gravity, so the cells closest to the ground move out of the way first
+        let direction = self.gravity.direction;
+        let iter_y = self.iter_axis(
+            ITERATION_ORD_Y,
+            0,
+            self.world.height,
+            direction == GravityDirection::Up,
+        );
+        let iter_x = self.iter_axis(
+            ITERATION_ORD_X,
+            x1,
+            x2,
+            direction == GravityDirection::Right,
+        );
         for y in iter_y {
             for x in &iter_x {
                 self.set_position(*x, y);
@@ -72,15 +180,28 @@ impl<'a> CellsApi<'a> {
         self.y = y as isize;
     }
     pub fn set_cell(&mut self, x: isize, y: isize, cell: CellType) {
+        let Ok(index) = self.locate(x, y) else {
+            return;
+        };
         let cell = Cell::new(cell);
-        *self.cell_by_offset(x, y) = cell;
-        let (x, y) = self.offset(x, y);
-        let index = (self.world.width as isize * y + x) as usize;
+        if self.world.material(index).rigid() || cell.material.rigid() {
+            self.world.bodies_dirty = true;
+        }
+        self.world.grid.set(index, cell);
         self.pixels[index * 4..index * 4 + 3].copy_from_slice(&cell.rgb);
     }
     #[inline]
-    pub fn current(&mut self) -> &mut Cell {
-        &mut self.world[(self.y as usize, self.x as usize)]
+    fn current_index(&self) -> usize {
+        self.y as usize * self.world.width + self.x as usize
+    }
+    #[inline]
+    pub fn current_material(&self) -> CellType {
+        self.world.material(self.current_index())
+    }
+    #[inline]
+    pub fn current(&mut self) -> &mut CellState {
+        let index = self.current_index();
+        self.world.state_mut(index)
     }
     #[inline]
     pub fn in_bounds(&mut self, x: isize, y: isize) -> bool {
@@ -88,31 +209,107 @@ impl<'a> CellsApi<'a> {
     }
     #[inline]
     fn offset(&mut self, x: isize, y: isize) -> (isize, isize) {
-        (self.x + x, self.y - y)
+        let (x, y) = self.gravity.direction.to_grid(x, y);
+        (self.x + x, self.y + y)
+    }
+    // Grid index of an offset, or the boundary it falls past
+    #[inline]
+    fn locate(&mut self, x: isize, y: isize) -> Result<usize, Boundary> {
+        let (x, y) = self.offset(x, y);
+        let (x, y) = self
+            .boundaries
+            .locate(x, y, self.world.width, self.world.height)?;
+        Ok(y * self.world.width + x)
+    }
+    #[inline]
+    pub fn material_by_offset(&mut self, x: isize, y: isize) -> CellType {
+        match self.locate(x, y) {
+            Ok(index) => self.world.material(index),
+            // Void edges look like open space, so things move off them
+            Err(Boundary::Void) => CellType::Air,
+            Err(_) => CellType::None,
+        }
     }
     #[inline]
-    pub fn cell_by_offset(&mut self, x: isize, y: isize) -> &mut Cell {
-        let (target_x, target_y) = self.offset(x, y);
-        if !self.in_bounds(target_x, target_y) {
-            self.none_cell = Cell::new(CellType::None);
-            return &mut self.none_cell;
+    pub fn state_by_offset(&mut self, x: isize, y: isize) -> &mut CellState {
+        match self.locate(x, y) {
+            Ok(index) => self.world.state_mut(index),
+            Err(_) => {
+                self.none_state = CellState::default();
+                &mut self.none_state
+            }
         }
-        &mut self.world[(target_y as usize, target_x as usize)]
     }
+    pub fn recolor(&mut self, x: isize, y: isize, rgb: [u8; 3]) {
+        let Ok(index) = self.locate(x, y) else {
+            return;
+        };
+        self.world.grid.colors[index] = rgb;
+        self.pixels[index * 4..index * 4 + 3].copy_from_slice(&rgb);
+    }
+    // Starts a spark in a resting conductor, returns if anything was sparked
+    pub fn spark(&mut self, x: isize, y: isize) -> bool {
+        let time = self.world.time;
+        if !self.material_by_offset(x, y).conductive() {
+            return false;
+        }
+        let target = self.state_by_offset(x, y);
+        if target.charge != 0 {
+            return false;
+        }
+        target.charge = SPARK_LENGTH + REFRACTORY_LENGTH;
+        // Sparks travel one cell per tick, no matter which way the grid is swept
+        target.updated = time;
+        true
+    }
+    pub fn sparking(&mut self, x: isize, y: isize) -> bool {
+        self.material_by_offset(x, y).conductive()
+            && self.state_by_offset(x, y).charge > REFRACTORY_LENGTH
+    }
+    // Whether any neighbouring conductor is sparking, devices use this to react to electricity
+    pub fn powered(&mut self) -> bool {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if (dx, dy) != (0, 0) && self.sparking(dx, dy) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+    // Rising and falling happen less often in weak gravity, strong gravity can fall two cells at once
     pub fn swap_offset(&mut self, x: isize, y: isize) {
-        let (target_x, target_y) = self.offset(x, y);
-        if !self.in_bounds(target_x, target_y) {
+        let strength = self.gravity.effective_strength();
+        if y != 0 && strength < 1.0 && fastrand::f32() >= strength {
             return;
         }
-        let current_index = self.y as usize * self.world.width + self.x as usize;
-        let target_index = target_y as usize * self.world.width + target_x as usize;
+        if y < 0 && strength > 1.0 && fastrand::f32() < strength - 1.0 {
+            let further = self.material_by_offset(x, y * 2);
+            if further == self.material_by_offset(x, y) {
+                self.swap_between(0, 0, x, y * 2);
+                return;
+            }
+        }
+        self.swap_between(0, 0, x, y);
+    }
+    // Swaps any two cells, as long as the first one hasn't already moved this tick
+    pub fn swap_between(&mut self, x1: isize, y1: isize, x2: isize, y2: isize) {
+        let Ok(current_index) = self.locate(x1, y1) else {
+            return;
+        };
+        let target_index = match self.locate(x2, y2) {
+            Ok(index) => index,
+            Err(Boundary::Void) => return self.fall_out(current_index),
+            Err(_) => return,
+        };
 
         // Stop material being simulated twice in a single frame
-        if self.world[current_index].updated == self.world.time {
+        let time = self.world.time;
+        if self.world.state_mut(current_index).updated == time {
             return;
         }
-        self.world[current_index].updated = self.world.time;
-        self.world[target_index].updated = self.world.time;
+        self.world.state_mut(current_index).updated = time;
+        self.world.state_mut(target_index).updated = time;
         // Slightly slower now, may be quicker when pixels are only stored in pixels
         // unsafe {
         //     let ptr_index = self.pixels.as_mut_ptr().add(current_index * 4);
@@ -123,13 +320,131 @@ impl<'a> CellsApi<'a> {
         // Somehow there are visual artifacts. Maybe race conditions?
 
         self.pixels[target_index * 4..target_index * 4 + 3]
-            .copy_from_slice(&self.world.grid[current_index].rgb);
+            .copy_from_slice(&self.world.grid.colors[current_index]);
         self.pixels[current_index * 4..current_index * 4 + 3]
-            .copy_from_slice(&self.world.grid[target_index].rgb);
+            .copy_from_slice(&self.world.grid.colors[target_index]);
         self.world.grid.swap(current_index, target_index);
     }
+    // Deletes a cell that moved into a void edge
+    fn fall_out(&mut self, index: usize) {
+        if self.world.state_mut(index).updated == self.world.time {
+            return;
+        }
+        let mut air = Cell::new(CellType::Air);
+        air.state.updated = self.world.time;
+        self.world.grid.set(index, air);
+        self.pixels[index * 4..index * 4 + 3].copy_from_slice(&air.rgb);
+    }
+    // Moves the current cell to any position, leaving air behind. Unlike `swap_offset` the target
+    // can be far away
+    pub fn move_offset(&mut self, x: isize, y: isize) {
+        let target_index = match self.locate(x, y) {
+            Ok(index) => index,
+            Err(Boundary::Void) => return self.set_cell(0, 0, CellType::Air),
+            Err(_) => return,
+        };
+        let mut cell = self.world.grid.get(self.current_index());
+        cell.state.updated = self.world.time;
+        self.world.grid.set(target_index, cell);
+        self.pixels[target_index * 4..target_index * 4 + 3].copy_from_slice(&cell.rgb);
+        self.set_cell(0, 0, CellType::Air);
+    }
+    // Drifts the current cell with the world's wind, returns if it moved
+    pub fn blow(&mut self, factor: f32) -> bool {
+        let (wind_x, wind_y) = self.world.wind;
+        let drift = |wind: f32| {
+            if fastrand::f32() < wind.abs() * factor {
+                wind.signum() as isize
+            } else {
+                0
+            }
+        };
+        // Wind doesn't turn with gravity
+        let (dx, dy) = self.absolute(drift(wind_x), drift(wind_y));
+        if (dx, dy) == (0, 0) || self.material_by_offset(dx, dy) != CellType::Air {
+            return false;
+        }
+        self.swap_between(0, 0, dx, dy);
+        true
+    }
+    // Knocks a cell out of the grid to fly as a particle. The velocity is in cells per tick and
+    // relative to gravity, like offsets
+    pub fn launch(&mut self, x: isize, y: isize, vx: f32, vy: f32) {
+        let Ok(index) = self.locate(x, y) else {
+            return;
+        };
+        let cell = self.world.grid.get(index);
+        let (vx, vy) = self.gravity.direction.to_grid(vx, vy);
+        let particle = Particle {
+            cell,
+            x: (index % self.world.width) as f32 + 0.5,
+            y: (index / self.world.width) as f32 + 0.5,
+            vx,
+            vy,
+        };
+        self.set_cell(x, y, CellType::Air);
+        self.launched.lock().unwrap().push(particle);
+    }
+    // Offset for a fixed direction on screen with y pointing up, whichever way gravity points
+    pub fn absolute(&self, x: isize, y: isize) -> (isize, isize) {
+        // Every gravity mapping is its own inverse
+        self.gravity.direction.to_grid(x, -y)
+    }
+    // Fills in gaps along source edges, only safe while no other thread is simulating
+    pub fn emit_sources(&mut self) {
+        let (width, height) = (self.world.width, self.world.height);
+        if width == 0 || height == 0 {
+            return;
+        }
+        let edges = [
+            (self.boundaries.top, (0..width).collect::<Vec<_>>()),
+            (
+                self.boundaries.bottom,
+                ((height - 1) * width..height * width).collect(),
+            ),
+            (
+                self.boundaries.left,
+                (0..height).map(|y| y * width).collect(),
+            ),
+            (
+                self.boundaries.right,
+                (0..height).map(|y| y * width + width - 1).collect(),
+            ),
+        ];
+        for (edge, indexes) in edges {
+            let Boundary::Source(material) = edge else {
+                continue;
+            };
+            for index in indexes {
+                if self.world.material(index) == CellType::Air
+                    && fastrand::u8(0..SOURCE_CHANCE) == 0
+                {
+                    let cell = Cell::new(material);
+                    if material.rigid() {
+                        self.world.bodies_dirty = true;
+                    }
+                    self.world.grid.set(index, cell);
+                    self.pixels[index * 4..index * 4 + 3].copy_from_slice(&cell.rgb);
+                }
+            }
+        }
+    }
     pub fn advance_time(&mut self) {
-        self.world.time = self.world.time.wrapping_add(1)
+        self.world.time = self.world.time.wrapping_add(1);
+        self.world.ticks += 1;
+    }
+    // Deterministic randomness for the current cell, only depends on the world seed, position,
+    // tick and salt
+    pub fn seeded_random(&self, salt: u64) -> u64 {
+        let mut z = self.world.seed
+            ^ (self.x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (self.y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+            ^ self.world.ticks.wrapping_mul(0x1656_67B1_9E37_79F9)
+            ^ salt;
+        // splitmix64 finalizer
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
     }
 }
 
@@ -139,14 +454,23 @@ pub struct SharedCellApi<'a> {
     pub world: &'a mut World,
     pub main_barrier: Barrier,
     pub sync_barrier: Barrier,
+    pub finish_barrier: Barrier,
+    // Particles knocked loose this call, added to the world once every thread is done
+    pub launched: Mutex<Vec<Particle>>,
+    pub deferred: Mutex<Vec<(usize, usize)>>,
+    pub threaded: bool,
 }
 impl<'a> SharedCellApi<'a> {
-    pub fn new(world: &'a mut World, pixels: &'a mut [u8], threads: usize) -> Self {
+    pub fn new(world: &'a mut World, pixels: &'a mut [u8], threads: usize, threaded: bool) -> Self {
         Self {
             world,
             pixels,
             main_barrier: Barrier::new(threads + 1),
             sync_barrier: Barrier::new(threads + 1),
+            finish_barrier: Barrier::new(threads + 1),
+            launched: Mutex::new(vec![]),
+            deferred: Mutex::new(vec![]),
+            threaded,
         }
     }
 }
@@ -0,0 +1,472 @@
+use std::collections::VecDeque;
+
+use super::{
+    boundary::Boundary,
+    cells::{Cell, CellType},
+    world::World,
+};
+
+// Largest group of cells that can break off into a body, anything bigger stays part of the terrain
+const MAX_BODY_CELLS: usize = 4096;
+// In cells per tick
+const GRAVITY: f32 = 0.1;
+const MAX_FALL_SPEED: f32 = 2.0;
+// A body that has not moved for this many ticks is merged back into the grid
+const SETTLE_TICKS: u32 = 60;
+
+// A group of connected solid cells that moves as one. Cells are kept in body space and drawn
+// back into the grid at the current position and angle every tick, once every strip is done
+pub struct RigidBody {
+    cells: Vec<Option<Cell>>,
+    width: usize,
+    height: usize,
+    // Centre of mass in body space
+    center_x: f32,
+    center_y: f32,
+    // Centre of mass in world space, y points down like the grid
+    x: f32,
+    y: f32,
+    // How fast the body is falling, along whichever way gravity points
+    speed: f32,
+    angle: f32,
+    spin: f32,
+    resting: u32,
+    // Grid index and body cell index of everything drawn last tick
+    footprint: Vec<(usize, usize)>,
+    // Grid cells the body was lifted out of this tick, where liquid it sinks into ends up
+    vacated: Vec<usize>,
+}
+impl RigidBody {
+    // Takes world positions of cells, which should be connected
+    fn from_cells(cells: Vec<(usize, usize, Cell)>, speed: f32, angle: f32) -> Self {
+        let min_x = cells.iter().map(|(x, _, _)| *x).min().unwrap_or(0);
+        let min_y = cells.iter().map(|(_, y, _)| *y).min().unwrap_or(0);
+        let width = cells
+            .iter()
+            .map(|(x, _, _)| x - min_x + 1)
+            .max()
+            .unwrap_or(1);
+        let height = cells
+            .iter()
+            .map(|(_, y, _)| y - min_y + 1)
+            .max()
+            .unwrap_or(1);
+
+        let mut body_cells = vec![None; width * height];
+        let (mut sum_x, mut sum_y) = (0.0, 0.0);
+        for (x, y, cell) in &cells {
+            body_cells[(y - min_y) * width + (x - min_x)] = Some(*cell);
+            sum_x += (x - min_x) as f32 + 0.5;
+            sum_y += (y - min_y) as f32 + 0.5;
+        }
+        let count = cells.len().max(1) as f32;
+        let (center_x, center_y) = (sum_x / count, sum_y / count);
+        Self {
+            cells: body_cells,
+            width,
+            height,
+            center_x,
+            center_y,
+            x: min_x as f32 + center_x,
+            y: min_y as f32 + center_y,
+            speed,
+            angle,
+            spin: 0.0,
+            resting: 0,
+            footprint: vec![],
+            vacated: vec![],
+        }
+    }
+    // Every grid cell covered at a given pose, paired with the body cell drawn there. Works
+    // backwards from the grid so rotated bodies have no holes. Cells past a void edge are left
+    // out, None if the body would go through any other edge
+    fn cover(&self, world: &World, x: f32, y: f32, angle: f32) -> Option<Vec<(usize, usize)>> {
+        let (sin, cos) = angle.sin_cos();
+        // Bounding box of the rotated body
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (x, y, x, y);
+        for (corner_x, corner_y) in [
+            (0.0, 0.0),
+            (self.width as f32, 0.0),
+            (0.0, self.height as f32),
+            (self.width as f32, self.height as f32),
+        ] {
+            let (dx, dy) = (corner_x - self.center_x, corner_y - self.center_y);
+            let (rotated_x, rotated_y) = (x + cos * dx - sin * dy, y + sin * dx + cos * dy);
+            min_x = min_x.min(rotated_x);
+            min_y = min_y.min(rotated_y);
+            max_x = max_x.max(rotated_x);
+            max_y = max_y.max(rotated_y);
+        }
+        let mut covered = vec![];
+        for world_y in min_y.floor() as isize..max_y.ceil() as isize {
+            for world_x in min_x.floor() as isize..max_x.ceil() as isize {
+                let dx = world_x as f32 + 0.5 - x;
+                let dy = world_y as f32 + 0.5 - y;
+                let local_x = (cos * dx + sin * dy + self.center_x).floor();
+                let local_y = (-sin * dx + cos * dy + self.center_y).floor();
+                if local_x < 0.0
+                    || local_y < 0.0
+                    || local_x >= self.width as f32
+                    || local_y >= self.height as f32
+                {
+                    continue;
+                }
+                let local = local_y as usize * self.width + local_x as usize;
+                if self.cells[local].is_none() {
+                    continue;
+                }
+                match world
+                    .boundaries
+                    .locate(world_x, world_y, world.width, world.height)
+                {
+                    Ok((x, y)) => covered.push((y * world.width + x, local)),
+                    Err(Boundary::Void) => {}
+                    Err(_) => return None,
+                }
+            }
+        }
+        Some(covered)
+    }
+    fn fits(&self, world: &World, x: f32, y: f32, angle: f32) -> Option<Vec<(usize, usize)>> {
+        let covered = self.cover(world, x, y, angle)?;
+        covered
+            .iter()
+            .all(|(index, local)| self.moves_into(world, *index, *local))
+            .then_some(covered)
+    }
+    // Whether the body cell can be drawn over whatever is in the grid cell
+    fn moves_into(&self, world: &World, index: usize, local: usize) -> bool {
+        let material = world.material(index);
+        material.passable()
+            || self.cells[local].is_some_and(|cell| cell.material.sinks_through(material))
+    }
+    // Splits off every connected group of cells that survived, or nothing if all are gone
+    fn split(self) -> Vec<RigidBody> {
+        let mut seen = vec![false; self.cells.len()];
+        let mut bodies = vec![];
+        let (sin, cos) = self.angle.sin_cos();
+        for start in 0..self.cells.len() {
+            if seen[start] || self.cells[start].is_none() {
+                continue;
+            }
+            seen[start] = true;
+            let mut queue = VecDeque::from([start]);
+            let mut group = vec![];
+            while let Some(index) = queue.pop_front() {
+                let (x, y) = (index % self.width, index / self.width);
+                group.push((x, y, self.cells[index].unwrap()));
+                let neighbours = [
+                    (x > 0).then(|| index - 1),
+                    (x + 1 < self.width).then(|| index + 1),
+                    (y > 0).then(|| index - self.width),
+                    (y + 1 < self.height).then(|| index + self.width),
+                ];
+                for next in neighbours.into_iter().flatten() {
+                    if !seen[next] && self.cells[next].is_some() {
+                        seen[next] = true;
+                        queue.push_back(next);
+                    }
+                }
+            }
+            // Keep the new body where the cells already were
+            let mut body = RigidBody::from_cells(group, self.speed, self.angle);
+            let offset_x = body.x - self.center_x;
+            let offset_y = body.y - self.center_y;
+            body.x = self.x + cos * offset_x - sin * offset_y;
+            body.y = self.y + sin * offset_x + cos * offset_y;
+            body.spin = self.spin;
+            bodies.push(body);
+        }
+        bodies
+    }
+    fn step(&mut self, world: &World) {
+        let strength = world.gravity.effective_strength();
+        if strength == 0.0 {
+            self.resting += 1;
+            return;
+        }
+        let (down_x, down_y) = world.gravity.direction.down();
+        let (down_x, down_y) = (down_x as f32, down_y as f32);
+        self.speed = (self.speed + GRAVITY * strength).min(MAX_FALL_SPEED);
+        let mut landed = false;
+        let mut remaining = self.speed;
+        while remaining > 0.0 {
+            let step = remaining.min(1.0);
+            let (x, y) = (self.x + down_x * step, self.y + down_y * step);
+            if self.fits(world, x, y, self.angle).is_none() {
+                landed = true;
+                break;
+            }
+            (self.x, self.y) = (x, y);
+            remaining -= step;
+        }
+        if !landed {
+            self.resting = 0;
+            return;
+        }
+        self.speed = 0.0;
+
+        // Tip over when everything holding the body up is off to one side of its centre
+        let covered = self
+            .cover(world, self.x, self.y, self.angle)
+            .unwrap_or_default();
+        let contacts = covered
+            .iter()
+            .filter(|(index, local)| match world.below(*index) {
+                Ok(below) => !self.moves_into(world, below, *local),
+                Err(edge) => edge != Boundary::Void,
+            })
+            .map(|(index, _)| {
+                // Distance to the side of the centre, across the direction of gravity
+                let dx = (index % world.width) as f32 + 0.5 - self.x;
+                let dy = (index / world.width) as f32 + 0.5 - self.y;
+                dx * down_y - dy * down_x
+            });
+        let (mut left, mut right) = (f32::MAX, f32::MIN);
+        for offset in contacts {
+            left = left.min(offset);
+            right = right.max(offset);
+        }
+        let torque = if left > 0.5 {
+            -0.01
+        } else if right < -0.5 && right != f32::MIN {
+            0.01
+        } else {
+            0.0
+        };
+        self.spin = (self.spin + torque).clamp(-0.05, 0.05);
+        if torque == 0.0 {
+            self.spin = 0.0;
+        }
+        if self.spin != 0.0 {
+            let angle = self.angle + self.spin;
+            if self.fits(world, self.x, self.y, angle).is_some() {
+                self.angle = angle;
+                self.resting = 0;
+                return;
+            }
+            self.spin = 0.0;
+        }
+        self.resting += 1;
+    }
+}
+
+impl World {
+    // Bodies are taken out of the grid, moved a tick and drawn back in
+    pub fn step_bodies(&mut self, pixels: &mut [u8]) {
+        let mut bodies = vec![];
+        for mut body in std::mem::take(&mut self.bodies) {
+            let mut damaged = false;
+            for (index, local) in std::mem::take(&mut body.footprint) {
+                body.vacated.push(index);
+                let Some(cell) = body.cells[local] else {
+                    continue;
+                };
+                if self.material(index) == cell.material {
+                    // Keep whatever happened to the cell in the grid, like heat or acid damage
+                    body.cells[local] = Some(self.grid.get(index));
+                    self.set_pixel(index, Cell::new(CellType::Air), pixels);
+                } else {
+                    // Burnt, dissolved or blown up while it was in the grid
+                    body.cells[local] = None;
+                    damaged = true;
+                }
+            }
+            if damaged {
+                self.bodies_dirty = true;
+                let vacated = std::mem::take(&mut body.vacated);
+                let mut parts = body.split();
+                if let Some(first) = parts.first_mut() {
+                    first.vacated = vacated;
+                }
+                bodies.extend(parts);
+            } else {
+                bodies.push(body);
+            }
+        }
+
+        // Looking for bodies means scanning the whole grid, so it only happens every few ticks, and
+        // not at all in worlds without rigid cells. Without gravity nothing needs holding up
+        let detect = match self.bodies_dirty {
+            true => self.ticks & 7 == 0,
+            false => self.ticks & 63 == 0 && self.rigid_cells > 0,
+        };
+        if detect && self.gravity.effective_strength() > 0.0 {
+            self.bodies_dirty = false;
+            bodies.extend(self.detect_bodies(pixels));
+        }
+
+        let mut remaining = vec![];
+        for mut body in bodies {
+            body.step(self);
+            // A body only moves where it fits, but the grid may have filled in around it since. It
+            // only draws over cells it can move into, those without room are drawn once there is
+            let mut covered = body
+                .cover(self, body.x, body.y, body.angle)
+                .unwrap_or_default();
+            // Bodies that have fallen out of the world entirely are gone
+            let gone = covered.is_empty();
+            covered.retain(|(index, local)| body.moves_into(self, *index, *local));
+            let mut displaced = vec![];
+            for (index, local) in &covered {
+                if let Some(cell) = body.cells[*local] {
+                    if !self.material(*index).passable() {
+                        displaced.push(self.grid.get(*index));
+                    }
+                    self.set_pixel(*index, cell, pixels);
+                }
+            }
+            // Liquid the body sank into takes the place it left
+            let mut vacated = std::mem::take(&mut body.vacated);
+            vacated.retain(|index| self.material(*index) == CellType::Air);
+            for (index, cell) in vacated.into_iter().zip(displaced) {
+                self.set_pixel(index, cell, pixels);
+            }
+            if body.resting < SETTLE_TICKS && !gone {
+                body.footprint = covered;
+                remaining.push(body);
+            } else if !gone {
+                // Settled back into the grid, where the next look for bodies counts its cells
+                self.bodies_dirty = true;
+            }
+        }
+        self.bodies = remaining;
+    }
+    // Finds groups of rigid cells with nothing holding them up and lifts them out of the grid
+    fn detect_bodies(&mut self, pixels: &mut [u8]) -> Vec<RigidBody> {
+        let mut seen = vec![false; self.grid.len()];
+        let mut bodies = vec![];
+        let mut queue = VecDeque::new();
+        self.rigid_cells = 0;
+        for start in 0..self.grid.len() {
+            if seen[start] || !self.material(start).rigid() {
+                continue;
+            }
+            seen[start] = true;
+            queue.push_back(start);
+            let mut group = vec![];
+            let mut anchored = false;
+            while let Some(index) = queue.pop_front() {
+                let (x, y) = (index % self.width, index / self.width);
+                group.push(index);
+                self.rigid_cells += 1;
+                let supported = match self.below(index) {
+                    Ok(below) => {
+                        let material = self.material(below);
+                        !material.rigid()
+                            && !material.passable()
+                            && !self.material(index).sinks_through(material)
+                    }
+                    Err(edge) => edge != Boundary::Void,
+                };
+                if supported {
+                    anchored = true;
+                }
+                let neighbours = [
+                    (x > 0).then(|| index - 1),
+                    (x + 1 < self.width).then(|| index + 1),
+                    (y > 0).then(|| index - self.width),
+                    (y + 1 < self.height).then(|| index + self.width),
+                ];
+                for next in neighbours.into_iter().flatten() {
+                    if !seen[next] && self.material(next).rigid() {
+                        seen[next] = true;
+                        queue.push_back(next);
+                    }
+                }
+            }
+            if anchored || group.len() > MAX_BODY_CELLS {
+                continue;
+            }
+            let cells = group
+                .iter()
+                .map(|index| {
+                    (
+                        index % self.width,
+                        index / self.width,
+                        self.grid.get(*index),
+                    )
+                })
+                .collect();
+            for index in &group {
+                self.set_pixel(*index, Cell::new(CellType::Air), pixels);
+            }
+            let mut body = RigidBody::from_cells(cells, 0.0, 0.0);
+            body.vacated = group;
+            bodies.push(body);
+        }
+        bodies
+    }
+    // Grid contents no longer match the bodies, e.g. after loading. Anything that was part of a
+    // body is now plain terrain until it is detected again
+    pub fn reset_bodies(&mut self) {
+        self.bodies.clear();
+        self.bodies_dirty = true;
+    }
+    // The cell gravity pulls this one towards, or the boundary in the way
+    fn below(&self, index: usize) -> Result<usize, Boundary> {
+        let (down_x, down_y) = self.gravity.direction.down();
+        let x = (index % self.width) as isize + down_x;
+        let y = (index / self.width) as isize + down_y;
+        let (x, y) = self.boundaries.locate(x, y, self.width, self.height)?;
+        Ok(y * self.width + x)
+    }
+    pub(super) fn set_pixel(&mut self, index: usize, cell: Cell, pixels: &mut [u8]) {
+        self.grid.set(index, cell);
+        pixels[index * 4..index * 4 + 3].copy_from_slice(&cell.rgb);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_blocks_fall_to_the_floor() {
+        fastrand::seed(7);
+        let mut world = World::new(20, 40, 1);
+        for y in 5..8 {
+            for x in 8..12 {
+                let index = y * world.width + x;
+                world.grid.set(index, Cell::new(CellType::Stone));
+            }
+        }
+        world.bodies_dirty = true;
+        let mut pixels = vec![0; world.width * world.height * 4];
+        world.simulate(200, &mut pixels);
+        assert!(world.bodies.is_empty(), "the block never settled");
+        let floor = world.height - 3..world.height;
+        let stone = floor
+            .flat_map(|y| (0..world.width).map(move |x| (x, y)))
+            .filter(|(x, y)| world.grid.material(y * world.width + x) == CellType::Stone);
+        assert_eq!(stone.count(), 12);
+        assert_eq!(world.rigid_cells, 12);
+    }
+
+    #[test]
+    fn blocks_sink_through_water() {
+        fastrand::seed(7);
+        let mut world = World::new(20, 40, 1);
+        for index in 20 * world.width..world.grid.len() {
+            world.grid.set(index, Cell::new(CellType::Water));
+        }
+        for y in 17..20 {
+            for x in 8..12 {
+                let index = y * world.width + x;
+                world.grid.set(index, Cell::new(CellType::Stone));
+            }
+        }
+        world.bodies_dirty = true;
+        let mut pixels = vec![0; world.width * world.height * 4];
+        world.simulate(300, &mut pixels);
+        assert!(world.bodies.is_empty(), "the block never settled");
+        let count = |material, rows: std::ops::Range<usize>| {
+            rows.flat_map(|y| (0..world.width).map(move |x| y * world.width + x))
+                .filter(|index| world.grid.material(*index) == material)
+                .count()
+        };
+        assert_eq!(count(CellType::Stone, world.height - 3..world.height), 12);
+        // The water it sank through was pushed up above it, not destroyed
+        assert_eq!(count(CellType::Water, 0..world.height), 20 * world.width);
+    }
+}
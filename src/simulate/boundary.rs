@@ -0,0 +1,101 @@
+use super::cells::CellType;
+
+// What happens to cells at the edges of the world
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Boundary {
+    // Nothing gets past, as if the world was surrounded by `CellType::None`
+    #[default]
+    Wall,
+    // Looks like open air, anything that moves into it is deleted
+    Void,
+    // Joined to the opposite edge
+    Wrap,
+    // A wall that keeps spilling this material into the world
+    Source(CellType),
+}
+impl Boundary {
+    // `material` is what a new source edge emits
+    pub fn next(self, material: CellType) -> Self {
+        match self {
+            Boundary::Wall => Boundary::Void,
+            Boundary::Void => Boundary::Wrap,
+            Boundary::Wrap => Boundary::Source(material),
+            Boundary::Source(_) => Boundary::Wall,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+impl Edge {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "top" => Some(Edge::Top),
+            "bottom" => Some(Edge::Bottom),
+            "left" => Some(Edge::Left),
+            "right" => Some(Edge::Right),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Edge::Top => "top",
+            Edge::Bottom => "bottom",
+            Edge::Left => "left",
+            Edge::Right => "right",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Boundaries {
+    pub top: Boundary,
+    pub bottom: Boundary,
+    pub left: Boundary,
+    pub right: Boundary,
+}
+impl Boundaries {
+    pub fn get_mut(&mut self, edge: Edge) -> &mut Boundary {
+        match edge {
+            Edge::Top => &mut self.top,
+            Edge::Bottom => &mut self.bottom,
+            Edge::Left => &mut self.left,
+            Edge::Right => &mut self.right,
+        }
+    }
+    pub fn wraps_horizontally(&self) -> bool {
+        self.left == Boundary::Wrap || self.right == Boundary::Wrap
+    }
+    // Wraps a grid position around any wrapping edges. If it is still outside the grid, returns
+    // the boundary it went past instead, checking the sides before the top and bottom
+    #[inline]
+    pub fn locate(
+        &self,
+        mut x: isize,
+        mut y: isize,
+        width: usize,
+        height: usize,
+    ) -> Result<(usize, usize), Boundary> {
+        let (width, height) = (width as isize, height as isize);
+        if x < 0 || x >= width {
+            let edge = if x < 0 { self.left } else { self.right };
+            if edge != Boundary::Wrap {
+                return Err(edge);
+            }
+            x = x.rem_euclid(width);
+        }
+        if y < 0 || y >= height {
+            let edge = if y < 0 { self.top } else { self.bottom };
+            if edge != Boundary::Wrap {
+                return Err(edge);
+            }
+            y = y.rem_euclid(height);
+        }
+        Ok((x as usize, y as usize))
+    }
+}
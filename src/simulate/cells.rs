@@ -1,33 +1,171 @@
 include!(concat!(env!("OUT_DIR"), "/cell_settings.rs"));
 
-// #[repr(packed)]
-#[derive(Clone, Copy)]
-pub struct Cell {
-    pub material: CellType,
-    pub rgb: [u8; 3],
+impl CellType {
+    pub fn conductive(&self) -> bool {
+        matches!(self, CellType::Metal)
+    }
+    pub fn flammable(&self) -> bool {
+        matches!(self, CellType::Wood | CellType::Oil | CellType::Plant)
+    }
+    pub fn explosive(&self) -> bool {
+        matches!(self, CellType::Gunpowder | CellType::Explosive)
+    }
+    // Materials that never move on their own
+    pub fn solid(&self) -> bool {
+        matches!(
+            self,
+            CellType::None
+                | CellType::Stone
+                | CellType::Wood
+                | CellType::Cloner
+                | CellType::Metal
+                | CellType::Battery
+                | CellType::Ice
+                | CellType::Plant
+                | CellType::Explosive
+                | CellType::Fan
+                | CellType::Emitter
+                | CellType::Drain
+        )
+    }
+    // How easily wind and fans push this material around, from 0 (not at all) to 1
+    pub fn wind_factor(&self) -> f32 {
+        match self {
+            CellType::Gas | CellType::Smoke => 1.0,
+            CellType::Steam => 0.9,
+            CellType::Fire => 0.8,
+            CellType::Snow => 0.5,
+            CellType::Seed => 0.4,
+            CellType::Gunpowder => 0.2,
+            CellType::Sand | CellType::Salt => 0.15,
+            _ => 0.0,
+        }
+    }
+    // Materials that hold together and fall as one body when nothing supports them
+    pub fn rigid(&self) -> bool {
+        matches!(self, CellType::Stone | CellType::Wood | CellType::Metal)
+    }
+    // Materials a falling body can move through, overwriting them
+    pub fn passable(&self) -> bool {
+        matches!(
+            self,
+            CellType::Air | CellType::Gas | CellType::Steam | CellType::Fire | CellType::Smoke
+        )
+    }
+    // Liquids a falling body made of this material sinks through, swapping places with them. Wood
+    // floats on all of them
+    pub fn sinks_through(&self, liquid: CellType) -> bool {
+        matches!(self, CellType::Stone | CellType::Metal)
+            && matches!(
+                liquid,
+                CellType::Water
+                    | CellType::Saltwater
+                    | CellType::Oil
+                    | CellType::Lava
+                    | CellType::Acid
+            )
+    }
+    pub fn hot(&self) -> bool {
+        matches!(self, CellType::Fire | CellType::Lava)
+    }
+    pub fn cold(&self) -> bool {
+        matches!(self, CellType::Ice | CellType::Snow)
+    }
+    // Materials drawn with a colour that differs from their cell's to show their state
+    pub fn shaded(&self) -> bool {
+        matches!(
+            self,
+            CellType::Fire
+                | CellType::Wood
+                | CellType::Oil
+                | CellType::Water
+                | CellType::Saltwater
+                | CellType::Lava
+        )
+    }
+    // How much damage acid does to this material each time it eats at it, 0 means immune
+    pub fn dissolve_rate(&self) -> u16 {
+        match self {
+            CellType::Stone => 2,
+            CellType::Metal | CellType::Battery | CellType::Fan => 3,
+            CellType::Oil => 6,
+            CellType::Wood | CellType::Plant | CellType::Seed | CellType::Explosive => 24,
+            CellType::Sand
+            | CellType::Salt
+            | CellType::WetSand
+            | CellType::Mud
+            | CellType::Gunpowder => 32,
+            _ => 0,
+        }
+    }
+}
+
+// Everything about a cell besides its material and colour. The grid keeps those three apart, see
+// `Grid`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CellState {
     pub selected: bool,
     pub updated: u8,
     pub discolored: bool,
     pub health: u16,
     pub lifespan: u16,
+    // Electrical state of conductors, counts down through sparking and then the refractory period
+    pub charge: u8,
+    // Which of `api::DIRECTIONS` a fan blows towards
+    pub direction: u8,
+    // Index into `CellType::ALL` of the material an emitter produces
+    pub emits: u8,
+    // How many ticks out of `Cell::MAX_RATE` an emitter produces something
+    pub rate: u8,
+}
+impl Default for CellState {
+    fn default() -> Self {
+        Self {
+            selected: false,
+            updated: 0,
+            discolored: false,
+            health: 0,
+            lifespan: 0,
+            charge: 0,
+            direction: 0,
+            emits: CellType::Air as u8,
+            rate: 0,
+        }
+    }
+}
+impl CellState {
+    pub fn emitted(&self) -> CellType {
+        CellType::ALL[self.emits as usize]
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Cell {
+    pub material: CellType,
+    pub rgb: [u8; 3],
+    pub state: CellState,
 }
 impl Cell {
+    pub const MAX_RATE: u8 = 16;
     pub fn new(material: CellType) -> Self {
         let rgb = Self::rgb_ranges(material);
         Self {
             material,
             rgb,
-            selected: false,
-            updated: 0,
-            // velocity_x: 0,
-            // velocity_y: 0,
-            health: 0,
-            discolored: false,
-            lifespan: 0,
-            // position_x: 0,
-            // position_y: 0,
+            state: CellState {
+                lifespan: material
+                    .decay()
+                    .map_or(0, |(min, max, _)| fastrand::u16(min..=max)),
+                ..CellState::default()
+            },
         }
     }
+    pub fn emitter(emits: CellType, rate: u8) -> Self {
+        let mut cell = Self::new(CellType::Emitter);
+        cell.state.emits = emits as u8;
+        cell.state.rate = rate.min(Self::MAX_RATE);
+        cell
+    }
 
     pub fn rgb_ranges(material: CellType) -> [u8; 3] {
         let random_variance = fastrand::i16(0..=100);
@@ -42,10 +180,56 @@ impl Cell {
         });
         rgb
     }
-    pub fn cfg_switch(&mut self, bool: bool) {
-        *self = match bool {
-            _ => Self::new(CellType::Air),
-            _ => *self,
+    // Colour a `shaded` material is drawn with. Cheap hashes of the position and time stand in for
+    // randomness so drawing the same frame twice looks the same. Kept out of line so drawing plain
+    // cells stays a straight copy
+    #[inline(never)]
+    pub fn shade(&self, x: usize, y: usize, time: u8) -> [u8; 3] {
+        let noise = || {
+            let hash = (x as u32).wrapping_mul(0x9e37_79b1)
+                ^ (y as u32).wrapping_mul(0x85eb_ca77)
+                ^ (time as u32).wrapping_mul(0xc2b2_ae3d);
+            (hash ^ hash >> 15).wrapping_mul(0x2c1b_3c6d) >> 24
         };
+        // Rises and falls between 0 and 15
+        let wave = |phase: usize| {
+            let step = phase as u8 & 31;
+            step.min(31 - step)
+        };
+        match self.material {
+            CellType::Fire => {
+                let [start, end] = self.material.color();
+                let blend = noise() as u16;
+                let mut rgb = [0; 3];
+                for (index, value) in rgb.iter_mut().enumerate() {
+                    *value = ((start[index] as u16 * (255 - blend) + end[index] as u16 * blend)
+                        / 255) as u8;
+                }
+                rgb
+            }
+            CellType::Wood | CellType::Oil if self.state.health > 0 || self.state.discolored => {
+                // Charring darkens the cell, burning ones glow as well
+                let char = self.state.health.min(512) as u32 / 3;
+                let mut rgb = self
+                    .rgb
+                    .map(|value| (value as u32 * (256 - char) / 256) as u8);
+                if self.state.discolored {
+                    rgb[0] = rgb[0].saturating_add(noise() as u8 / 4);
+                }
+                rgb
+            }
+            CellType::Water | CellType::Saltwater => {
+                let shimmer = wave(x + y / 2 + time as usize / 2) / 2;
+                self.rgb.map(|value| value.saturating_add(shimmer))
+            }
+            CellType::Lava => {
+                let pulse = wave(time as usize / 2 + (x + y) / 8) * 2;
+                let mut rgb = self.rgb;
+                rgb[1] = rgb[1].saturating_add(pulse * 2);
+                rgb[2] = rgb[2].saturating_add(pulse / 2);
+                rgb
+            }
+            _ => self.rgb,
+        }
     }
 }
@@ -0,0 +1,89 @@
+use super::cells::Cell;
+
+// Alternative ways of drawing the grid, mostly useful for tracking down simulation bugs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebugView {
+    #[default]
+    Normal,
+    Updated,
+    Health,
+    Lifespan,
+    Discolored,
+    Threads,
+}
+impl DebugView {
+    const ORDER: [DebugView; 6] = [
+        DebugView::Normal,
+        DebugView::Updated,
+        DebugView::Health,
+        DebugView::Lifespan,
+        DebugView::Discolored,
+        DebugView::Threads,
+    ];
+    pub fn next(self) -> Self {
+        let index = Self::ORDER
+            .iter()
+            .position(|view| *view == self)
+            .unwrap_or(0);
+        Self::ORDER[(index + 1) % Self::ORDER.len()]
+    }
+    // Color of a single cell, `strip` is the index of the thread that owns the column
+    pub fn color(self, cell: &Cell, time: u8, strip: usize) -> [u8; 3] {
+        match self {
+            DebugView::Normal => cell.rgb,
+            DebugView::Updated => {
+                if cell.state.updated == time {
+                    [255, 40, 40]
+                } else {
+                    dim(cell.rgb)
+                }
+            }
+            DebugView::Health => heatmap(cell.state.health, 512),
+            DebugView::Lifespan => heatmap(cell.state.lifespan, 2048),
+            DebugView::Discolored => {
+                if cell.state.discolored {
+                    [255, 0, 255]
+                } else {
+                    dim(cell.rgb)
+                }
+            }
+            DebugView::Threads => {
+                let tint = STRIP_COLORS[strip % STRIP_COLORS.len()];
+                let [r, g, b] = dim(cell.rgb);
+                [
+                    r / 2 + tint[0] / 2,
+                    g / 2 + tint[1] / 2,
+                    b / 2 + tint[2] / 2,
+                ]
+            }
+        }
+    }
+}
+
+const STRIP_COLORS: [[u8; 3]; 8] = [
+    [230, 25, 75],
+    [60, 180, 75],
+    [255, 225, 25],
+    [0, 130, 200],
+    [245, 130, 48],
+    [145, 30, 180],
+    [70, 240, 240],
+    [240, 50, 230],
+];
+
+fn dim(rgb: [u8; 3]) -> [u8; 3] {
+    let grey = ((rgb[0] as u16 + rgb[1] as u16 + rgb[2] as u16) / 3 / 3) as u8;
+    [grey, grey, grey]
+}
+
+// Black -> red -> yellow -> white as the value approaches max
+fn heatmap(value: u16, max: u16) -> [u8; 3] {
+    if value == 0 {
+        return [0, 0, 0];
+    }
+    let t = (value.min(max) as u32 * 765 / max as u32) as u16;
+    let r = t.min(255) as u8;
+    let g = t.saturating_sub(255).min(255) as u8;
+    let b = t.saturating_sub(510).min(255) as u8;
+    [r.max(40), g, b]
+}
@@ -0,0 +1,73 @@
+use std::ops::Neg;
+
+// Which way things fall. Material rules are written as if gravity points down, `CellsApi` turns
+// their offsets to match the real direction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GravityDirection {
+    #[default]
+    Down,
+    Left,
+    Up,
+    Right,
+    Zero,
+}
+impl GravityDirection {
+    const ORDER: [GravityDirection; 5] = [
+        GravityDirection::Down,
+        GravityDirection::Left,
+        GravityDirection::Up,
+        GravityDirection::Right,
+        GravityDirection::Zero,
+    ];
+    pub fn next(self) -> Self {
+        let index = Self::ORDER
+            .iter()
+            .position(|direction| *direction == self)
+            .unwrap_or(0);
+        Self::ORDER[(index + 1) % Self::ORDER.len()]
+    }
+    // Grid offset of the cell below, with y counting rows downwards
+    pub fn down(self) -> (isize, isize) {
+        match self {
+            GravityDirection::Down | GravityDirection::Zero => (0, 1),
+            GravityDirection::Left => (-1, 0),
+            GravityDirection::Up => (0, -1),
+            GravityDirection::Right => (1, 0),
+        }
+    }
+    // Turns an offset or velocity relative to gravity, where y = -1 is below, into a grid one
+    #[inline]
+    pub fn to_grid<T: Neg<Output = T>>(self, x: T, y: T) -> (T, T) {
+        match self {
+            GravityDirection::Down | GravityDirection::Zero => (x, -y),
+            GravityDirection::Left => (y, x),
+            GravityDirection::Up => (-x, y),
+            GravityDirection::Right => (-y, -x),
+        }
+    }
+}
+
+// Strength scales how often things fall, 0 floats, 1 is normal and 2 falls twice as fast
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gravity {
+    pub direction: GravityDirection,
+    pub strength: f32,
+}
+impl Default for Gravity {
+    fn default() -> Self {
+        Self {
+            direction: GravityDirection::Down,
+            strength: 1.0,
+        }
+    }
+}
+impl Gravity {
+    pub const MAX_STRENGTH: f32 = 2.0;
+    // Strength that actually applies, zero gravity ignores the setting
+    pub fn effective_strength(&self) -> f32 {
+        match self.direction {
+            GravityDirection::Zero => 0.0,
+            _ => self.strength,
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use super::cells::{Cell, CellState, CellType};
+
+// Cells stored as one array per part. Most of the simulation only looks at materials, which are a
+// byte each this way, so sweeping the grid goes through far less memory than whole cells would.
+// State is kept as one struct per cell, as giving `updated` its own array too made simulating
+// about 15% slower
+#[derive(Clone)]
+pub struct Grid {
+    pub materials: Vec<CellType>,
+    pub colors: Vec<[u8; 3]>,
+    pub states: Vec<CellState>,
+}
+impl Grid {
+    pub fn new(len: usize, cell: Cell) -> Self {
+        Self {
+            materials: vec![cell.material; len],
+            colors: vec![cell.rgb; len],
+            states: vec![cell.state; len],
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+    #[inline]
+    pub fn get(&self, index: usize) -> Cell {
+        Cell {
+            material: self.materials[index],
+            rgb: self.colors[index],
+            state: self.states[index],
+        }
+    }
+    #[inline]
+    pub fn material(&self, index: usize) -> CellType {
+        self.materials[index]
+    }
+    #[inline]
+    pub fn set(&mut self, index: usize, cell: Cell) {
+        self.materials[index] = cell.material;
+        self.colors[index] = cell.rgb;
+        self.states[index] = cell.state;
+    }
+    #[inline]
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.materials.swap(a, b);
+        self.colors.swap(a, b);
+        self.states.swap(a, b);
+    }
+    // Makes this grid the same as another, reusing its memory when they are the same size
+    pub fn copy_from(&mut self, other: &Grid) {
+        self.materials.clone_from(&other.materials);
+        self.colors.clone_from(&other.colors);
+        self.states.clone_from(&other.states);
+    }
+    // Whether a cell is any different in another grid of the same size
+    #[inline]
+    pub fn differs(&self, other: &Grid, index: usize) -> bool {
+        self.materials[index] != other.materials[index]
+            || self.colors[index] != other.colors[index]
+            || self.states[index] != other.states[index]
+    }
+    pub fn fill(&mut self, cell: Cell) {
+        self.materials.fill(cell.material);
+        self.colors.fill(cell.rgb);
+        self.states.fill(cell.state);
+    }
+    pub fn iter(&self) -> impl Iterator<Item = Cell> + '_ {
+        (0..self.len()).map(|index| self.get(index))
+    }
+}
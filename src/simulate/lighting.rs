@@ -0,0 +1,137 @@
+use super::{api::REFRACTORY_LENGTH, cells::CellType, grid::Grid, world::World};
+
+// Light left after crossing a single open cell
+const FALLOFF: f32 = 0.95;
+// Brightness of anywhere no light reaches
+const AMBIENT: f32 = 0.3;
+// Lit areas can end up brighter than the colours they are drawn with
+const MAX_BRIGHTNESS: f32 = 1.6;
+const BLOCK_SIZES: [usize; 4] = [1, 2, 4, 8];
+
+// Optional pass over the rendered frame where glowing cells light up their surroundings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lighting {
+    pub enabled: bool,
+    // Light is worked out for square blocks of cells, larger blocks are faster but blurrier
+    pub block_size: usize,
+}
+impl Default for Lighting {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            block_size: 4,
+        }
+    }
+}
+impl Lighting {
+    pub fn cycle_quality(&mut self) {
+        let index = BLOCK_SIZES
+            .iter()
+            .position(|size| *size == self.block_size)
+            .unwrap_or(0);
+        self.block_size = BLOCK_SIZES[(index + 1) % BLOCK_SIZES.len()];
+    }
+}
+
+fn emission(grid: &Grid, index: usize) -> Option<[f32; 3]> {
+    let glowing = match grid.materials[index] {
+        CellType::Fire | CellType::Lava => true,
+        CellType::Metal => grid.states[index].charge > REFRACTORY_LENGTH,
+        _ => false,
+    };
+    glowing.then(|| grid.colors[index].map(|value| value as f32 / 255.0))
+}
+fn opaque(material: CellType) -> bool {
+    material.solid() && material != CellType::Ice
+}
+
+impl World {
+    pub fn apply_lighting(&self, pixels: &mut [u8]) {
+        let size = self.lighting.block_size.max(1);
+        let blocks_x = self.width.div_ceil(size);
+        let blocks_y = self.height.div_ceil(size);
+        let mut light = vec![[0.0f32; 3]; blocks_x * blocks_y];
+        let mut opaque_cells = vec![0; blocks_x * blocks_y];
+        for (index, material) in self.grid.materials.iter().enumerate() {
+            let block = (index / self.width / size) * blocks_x + (index % self.width) / size;
+            if let Some(emitted) = emission(&self.grid, index) {
+                for (channel, value) in light[block].iter_mut().zip(emitted) {
+                    *channel = channel.max(value);
+                }
+            } else if opaque(*material) {
+                opaque_cells[block] += 1;
+            }
+        }
+        // How much light leaves each block, mostly solid blocks are lit but let nothing through
+        let falloff = FALLOFF.powi(size as i32);
+        let transmit: Vec<f32> = opaque_cells
+            .iter()
+            .map(|count| {
+                if *count * 2 > size * size {
+                    0.0
+                } else {
+                    falloff
+                }
+            })
+            .collect();
+
+        // Sweeps forwards then backwards spread light around corners as well as in straight lines
+        let spread = |light: &mut Vec<[f32; 3]>, block: usize, from: usize| {
+            let incoming = light[from].map(|value| value * transmit[from]);
+            for (channel, value) in light[block].iter_mut().zip(incoming) {
+                *channel = channel.max(value);
+            }
+        };
+        for _ in 0..2 {
+            for y in 0..blocks_y {
+                for x in 0..blocks_x {
+                    let block = y * blocks_x + x;
+                    if x > 0 {
+                        spread(&mut light, block, block - 1);
+                    }
+                    if y > 0 {
+                        spread(&mut light, block, block - blocks_x);
+                    }
+                }
+            }
+            for y in (0..blocks_y).rev() {
+                for x in (0..blocks_x).rev() {
+                    let block = y * blocks_x + x;
+                    if x + 1 < blocks_x {
+                        spread(&mut light, block, block + 1);
+                    }
+                    if y + 1 < blocks_y {
+                        spread(&mut light, block, block + blocks_x);
+                    }
+                }
+            }
+        }
+
+        // Blend between the four nearest blocks so large blocks don't show up as squares
+        let sample = |block_x: usize, block_y: usize| light[block_y * blocks_x + block_x];
+        for y in 0..self.height {
+            let fy = ((y as f32 + 0.5) / size as f32 - 0.5).max(0.0);
+            let (y0, ty) = (fy as usize, fy.fract());
+            let y1 = (y0 + 1).min(blocks_y - 1);
+            for x in 0..self.width {
+                let fx = ((x as f32 + 0.5) / size as f32 - 0.5).max(0.0);
+                let (x0, tx) = (fx as usize, fx.fract());
+                let x1 = (x0 + 1).min(blocks_x - 1);
+                let (a, b, c, d) = (
+                    sample(x0, y0),
+                    sample(x1, y0),
+                    sample(x0, y1),
+                    sample(x1, y1),
+                );
+                let index = (y * self.width + x) * 4;
+                for channel in 0..3 {
+                    let top = a[channel] + (b[channel] - a[channel]) * tx;
+                    let bottom = c[channel] + (d[channel] - c[channel]) * tx;
+                    let lit = (AMBIENT + top + (bottom - top) * ty).min(MAX_BRIGHTNESS);
+                    let value = &mut pixels[index + channel];
+                    *value = (*value as f32 * lit).min(255.0) as u8;
+                }
+            }
+        }
+    }
+}
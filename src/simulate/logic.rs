@@ -1,31 +1,147 @@
-use crate::simulate::cells::CellType::*;
+use crate::simulate::cells::{Cell, CellType, CellType::*};
 
-use super::api::CellsApi;
+use super::api::{circle_offsets, CellsApi, DIRECTIONS, REFRACTORY_LENGTH};
+
+// Total damage acid can do before it is used up
+const ACID_STRENGTH: u16 = 768;
+// Damage a cell takes before acid dissolves it
+const DISSOLVE_THRESHOLD: u16 = 256;
+
+// Growth budget of a freshly sprouted seed, plants also branch so they end up larger than this
+const PLANT_HEIGHT: u64 = 20;
+
+// Most explosive cells that join a single detonation, and the largest blast they can make
+const EXPLOSIVE_MASS_LIMIT: usize = 2048;
+const BLAST_RADIUS_LIMIT: isize = 40;
+// How fast debris is thrown per cell it was from the edge of the blast, and the fastest sparks
+const DEBRIS_SPEED: f32 = 0.25;
+const MAX_SPARK_SPEED: f32 = 3.0;
+// How far water has to fall freely to splash when it lands in more water, and how fast the
+// splash is thrown per square root of that distance
+const SPLASH_HEIGHT: u16 = 8;
+const SPLASH_SPEED: f32 = 0.4;
+
+// How far a fan blows, and how wide its stream is either side of the middle
+const FAN_RANGE: isize = 16;
+const FAN_SPREAD: isize = 1;
+
+// Chance out of this that a mixture open to the air dries out each tick
+const DRYING_CHANCE: u16 = 2048;
+// Neighbouring water cells that leave sand soaked through into mud straight away, sand touching
+// less water only gets damp and turns to mud once it takes in more
+const SATURATED: usize = 4;
+
+// How many connected liquid cells a surface cell searches through for somewhere lower to flow to
+const PRESSURE_SEARCH_LIMIT: usize = 1024;
 
 pub fn simulate_steps(api: &mut CellsApi) {
-    for i in 0..2 {}
-    match api.current().material {
-        Sand => {
+    let material = api.current_material();
+    // Materials with a lifespan count it down once a tick and turn into something else at zero
+    if let Some((_, _, target)) = material.decay() {
+        let time = api.world.time;
+        let cell = api.current();
+        if cell.updated != time {
+            if cell.lifespan == 0 {
+                api.set_cell(0, 0, target);
+                return;
+            }
+            cell.lifespan -= 1;
+        }
+    }
+    if api.world.wind != (0.0, 0.0) {
+        let factor = material.wind_factor();
+        if factor > 0.0 && api.blow(factor) {
+            return;
+        }
+    }
+    match material {
+        Sand | Salt => {
+            // Sand soaks up water it touches, salt dissolves into it
+            let (dx, dy) = (fastrand::isize(-1..=1), fastrand::isize(-1..=1));
+            if fastrand::u8(0..8) == 0 && api.material_by_offset(dx, dy) == Water {
+                if material == Sand {
+                    let soaked = touching_count(api, |m| *m == Water) >= SATURATED;
+                    api.set_cell(dx, dy, Air);
+                    api.set_cell(0, 0, if soaked { Mud } else { WetSand });
+                } else {
+                    api.set_cell(dx, dy, Saltwater);
+                    api.set_cell(0, 0, Air);
+                }
+                return;
+            }
             let x = fastrand::isize(-1..=1);
-            if api.cell_by_offset(0, -1).material == Air
-                || matches!(api.cell_by_offset(0, -1).material, Water | Oil) && fastrand::bool()
+            if api.material_by_offset(0, -1) == Air
+                || matches!(api.material_by_offset(0, -1), Water | Oil) && fastrand::bool()
             {
                 api.swap_offset(0, -1)
-            } else if api.cell_by_offset(x, -1).material == Air
-                || matches!(api.cell_by_offset(x, -1).material, Water | Oil) && fastrand::bool()
+            } else if api.material_by_offset(x, -1) == Air
+                || matches!(api.material_by_offset(x, -1), Water | Oil) && fastrand::bool()
             {
                 api.swap_offset(x, -1);
             }
         }
-        Water => {
+        Saltwater if fastrand::u8(0..4) == 0 && touching(api, CellType::hot) => boil_off(api, Salt),
+        WetSand | Mud if fastrand::u8(0..4) == 0 && touching(api, CellType::hot) => {
+            boil_off(api, if material == Mud { WetSand } else { Sand })
+        }
+        Water | Saltwater => {
             let x = fastrand::isize(-5..=5);
-            if matches!(api.cell_by_offset(0, -1).material, Air | Gas)
-                || (api.cell_by_offset(0, -1).material == Oil && fastrand::bool())
+            let below = api.material_by_offset(0, -1);
+            // Water keeps count of how far it has fallen freely in `lifespan`
+            let time = api.world.time;
+            let cell = api.current();
+            let fallen = std::mem::take(&mut cell.lifespan);
+            if matches!(below, Air | Gas) && cell.updated != time {
+                cell.lifespan = fallen.saturating_add(1);
+            } else if fallen >= SPLASH_HEIGHT && matches!(below, Water | Saltwater) {
+                splash(api, fallen);
+            }
+            if matches!(below, Air | Gas)
+                || (below == Oil && fastrand::bool())
+                // Salt makes water heavier, so it settles underneath
+                || (material == Saltwater && below == Water && fastrand::u8(0..4) == 0)
             {
                 api.swap_offset(0, -1)
-            } else if matches!(api.cell_by_offset(x, -1).material, Air | Oil | Gas | Fire) {
+            } else if matches!(api.material_by_offset(x, -1), Air | Oil | Gas | Fire) {
+                api.swap_offset(x, -1);
+            } else if matches!(api.material_by_offset(x, 0), Air | Oil | Gas | Fire) {
+                api.swap_offset(x, 0);
+            } else {
+                flow_pressure(api, material);
+            }
+        }
+        WetSand => {
+            if dry_out(api, Sand) {
+                return;
+            }
+            let (dx, dy) = (fastrand::isize(-1..=1), fastrand::isize(-1..=1));
+            if fastrand::u8(0..16) == 0 && api.material_by_offset(dx, dy) == Water {
+                api.set_cell(dx, dy, Air);
+                api.set_cell(0, 0, Mud);
+                return;
+            }
+            // Damp sand holds together, it only slides where the slope drops away by two cells so
+            // it piles up twice as steep as dry sand
+            let x = [-1, 1][fastrand::usize(0..2)];
+            if api.material_by_offset(0, -1) == Air
+                || matches!(api.material_by_offset(0, -1), Water | Oil) && fastrand::bool()
+            {
+                api.swap_offset(0, -1)
+            } else if api.material_by_offset(x, -1) == Air && api.material_by_offset(x, -2) == Air {
+                api.swap_offset(x, -1);
+            }
+        }
+        Mud => {
+            if dry_out(api, WetSand) || fastrand::u8(0..3) != 0 {
+                return;
+            }
+            // Only moves every few ticks, so it oozes rather than flows
+            let x = fastrand::isize(-1..=1);
+            if matches!(api.material_by_offset(0, -1), Air | Water | Oil) {
+                api.swap_offset(0, -1)
+            } else if api.material_by_offset(x, -1) == Air {
                 api.swap_offset(x, -1);
-            } else if matches!(api.cell_by_offset(x, 0).material, Air | Oil | Gas | Fire) {
+            } else if api.material_by_offset(x, 0) == Air {
                 api.swap_offset(x, 0);
             }
         }
@@ -33,7 +149,7 @@ pub fn simulate_steps(api: &mut CellsApi) {
             let mut to_clone = Air;
             for dx in -1..=1 {
                 for dy in -1..=1 {
-                    let target = api.cell_by_offset(dx, dy).material;
+                    let target = api.material_by_offset(dx, dy);
                     if target == Air {
                         api.set_cell(dx, dy, to_clone);
                     } else if !matches!(target, Cloner | Stone | Gas) {
@@ -42,40 +158,56 @@ pub fn simulate_steps(api: &mut CellsApi) {
                 }
             }
         }
-        Wood => {
-            if api.current().discolored {
-                let dx = fastrand::isize(-15..15) / 8;
-                let dy = fastrand::isize(-15..15) / 8;
-                if fastrand::u8(0..8) < 3 && api.cell_by_offset(dx, dy).material == Air {
-                    api.set_cell(dx, dy, Fire)
+        Emitter => {
+            let cell = *api.current();
+            if fastrand::u8(0..Cell::MAX_RATE) < cell.rate {
+                let (dx, dy) = [(0, -1), (-1, 0), (1, 0), (0, 1)][fastrand::usize(0..4)];
+                if api.material_by_offset(dx, dy) == Air {
+                    api.set_cell(dx, dy, cell.emitted());
                 }
             }
         }
-        Fire => {
-            let c = api.current();
-            c.health += 1;
-            if c.health > fastrand::u16(16..2048) {
-                api.set_cell(0, 0, Gas);
-            } else {
-                let dx = fastrand::isize(-15..=15) / 8;
-                let dy = fastrand::isize(-20..=25) / 8;
-                let target = api.cell_by_offset(dx, dy);
-                if target.material == Water {
-                    api.set_cell(0, 0, Gas);
-                    return;
+        // Anything loose that touches a drain disappears into it
+        Drain => {
+            for (dx, dy) in [(0, -1), (-1, 0), (1, 0), (0, 1)] {
+                let material = api.material_by_offset(dx, dy);
+                if material != Air && !material.solid() {
+                    api.set_cell(dx, dy, Air);
                 }
-                if matches!(target.material, Wood | Oil) {
-                    target.health += 5;
-                    if target.health > fastrand::u16(1..512) {
-                        api.set_cell(dx, dy, Fire);
-                    } else {
-                        target.discolored = true;
-                    }
+            }
+        }
+        Wood | Plant if api.current().discolored => {
+            let dx = fastrand::isize(-15..15) / 8;
+            let dy = fastrand::isize(-15..15) / 8;
+            if fastrand::u8(0..8) < 3 && api.material_by_offset(dx, dy) == Air {
+                api.set_cell(dx, dy, Fire)
+            }
+        }
+        Fire => {
+            let dx = fastrand::isize(-15..=15) / 8;
+            let dy = fastrand::isize(-20..=25) / 8;
+            let material = api.material_by_offset(dx, dy);
+            if material == Water {
+                api.set_cell(dx, dy, Steam);
+                api.set_cell(0, 0, Smoke);
+                return;
+            }
+            if material.flammable() {
+                let target = api.state_by_offset(dx, dy);
+                // A plant's health is the water it holds, which boils off before it starts to burn
+                if material == Plant && !target.discolored {
+                    target.health = 0;
                 }
-                if api.cell_by_offset(dx, dy).material == Air {
-                    api.swap_offset(dx, dy);
+                target.health += 5;
+                if target.health > fastrand::u16(1..512) {
+                    api.set_cell(dx, dy, Fire);
+                } else {
+                    target.discolored = true;
                 }
             }
+            if api.material_by_offset(dx, dy) == Air {
+                api.swap_offset(dx, dy);
+            }
         }
         Oil => {
             let x = fastrand::isize(-5..=5);
@@ -83,56 +215,821 @@ pub fn simulate_steps(api: &mut CellsApi) {
                 api.current().health += 1;
                 let dx = fastrand::isize(-15..=15) / 8;
                 let dy = fastrand::isize(-15..=15) / 8;
-                if fastrand::u8(0..8) < 3 && api.cell_by_offset(dx, dy).material == Air {
+                if fastrand::u8(0..8) < 3 && api.material_by_offset(dx, dy) == Air {
                     api.set_cell(dx, dy, Fire)
                 }
             }
-            if matches!(api.cell_by_offset(0, -1).material, Air | Fire | Gas) {
+            if matches!(api.material_by_offset(0, -1), Air | Fire | Gas) {
                 api.swap_offset(0, -1)
-            } else if matches!(api.cell_by_offset(x, -1).material, Air | Fire | Gas) {
+            } else if matches!(api.material_by_offset(x, -1), Air | Fire | Gas) {
                 api.swap_offset(x, -1);
-            } else if matches!(api.cell_by_offset(x, 0).material, Air | Fire | Gas) {
+            } else if matches!(api.material_by_offset(x, 0), Air | Fire | Gas) {
                 api.swap_offset(x, 0);
+            } else {
+                flow_pressure(api, Oil);
             }
         }
-        Gas => {
-            let c = api.current();
-            c.health += 1;
-            if c.health > fastrand::u16(8..1024) {
-                api.set_cell(0, 0, Air);
-            } else {
-                let dx = fastrand::isize(-9..=9) / 8;
-                let dy = fastrand::isize(-8..26) / 8;
-                let target = api.cell_by_offset(dx, dy);
-                if target.material == Air {
-                    api.swap_offset(dx, dy);
-                    return;
-                }
-                if target.material == Fire {
-                    api.set_cell(0, 0, Air)
-                }
+        Gas | Smoke => {
+            let dx = fastrand::isize(-9..=9) / 8;
+            let dy = fastrand::isize(-8..26) / 8;
+            let target = api.material_by_offset(dx, dy);
+            if target == Air {
+                api.swap_offset(dx, dy);
+                return;
+            }
+            if material == Gas && target == Fire {
+                api.set_cell(0, 0, Air)
             }
         }
         Lava => {
-            let c = api.current();
             let x = fastrand::isize(-4..=4);
-            if api.cell_by_offset(0, -1).material == Air {
+            if api.material_by_offset(0, -1) == Air {
                 api.swap_offset(0, -1)
-            } else if matches!(api.cell_by_offset(x, -1).material, Air) {
+            } else if matches!(api.material_by_offset(x, -1), Air) {
                 api.swap_offset(x, -1);
-            } else if matches!(api.cell_by_offset(x, 0).material, Air) {
+            } else if matches!(api.material_by_offset(x, 0), Air) {
                 api.swap_offset(x, 0);
+            } else {
+                flow_pressure(api, Lava);
             }
             for y in -1..=1 {
                 for x in -1..=1 {
-                    if api.cell_by_offset(x, y).material == Water { api.set_cell(x, y, Stone) }
+                    if api.material_by_offset(x, y) == Water {
+                        api.set_cell(x, y, Stone)
+                    }
                 }
             }
-            if api.cell_by_offset(0, 1).material == Air && fastrand::usize(0..16) == 0 {
+            if api.material_by_offset(0, 1) == Air && fastrand::usize(0..16) == 0 {
                 api.set_cell(0, 1, Fire);
-                api.cell_by_offset(0, 1).health = 100;
+                // Flames coming off lava die out quickly
+                api.state_by_offset(0, 1).lifespan /= 4;
+            }
+        }
+        Metal => {
+            let time = api.world.time;
+            let cell = api.current();
+            if cell.charge == 0 || cell.updated == time {
+                return;
+            }
+            let charge = cell.charge;
+            cell.charge -= 1;
+            if charge > REFRACTORY_LENGTH {
+                api.recolor(0, 0, [255, 244, 160]);
+                for (dx, dy) in [(0, -1), (-1, 0), (1, 0), (0, 1)] {
+                    api.spark(dx, dy);
+                    if api.material_by_offset(dx, dy).flammable() && fastrand::u8(0..8) == 0 {
+                        api.set_cell(dx, dy, Fire);
+                    }
+                }
+            } else if charge == REFRACTORY_LENGTH {
+                api.recolor(0, 0, Cell::rgb_ranges(Metal));
+            }
+        }
+        Acid => {
+            let dx = fastrand::isize(-1..=1);
+            let dy = fastrand::isize(-1..=0);
+            let target = api.material_by_offset(dx, dy);
+            let rate = target.dissolve_rate();
+            if rate > 0 {
+                // A plant's health is the water it holds, so acid eats plants away by chance at
+                // the same average rate instead of counting up damage in it
+                let dissolved = if target == Plant {
+                    fastrand::u16(0..DISSOLVE_THRESHOLD) < rate
+                } else {
+                    let health = &mut api.state_by_offset(dx, dy).health;
+                    *health = health.saturating_add(rate);
+                    *health > DISSOLVE_THRESHOLD
+                };
+                if dissolved {
+                    // Stone crumbles instead of vanishing outright
+                    let residue = if target == Stone { Sand } else { Gas };
+                    api.set_cell(dx, dy, residue);
+                }
+                let acid = api.current();
+                acid.health += rate;
+                if acid.health > ACID_STRENGTH {
+                    api.set_cell(0, 0, Gas);
+                    return;
+                }
+            }
+            let x = fastrand::isize(-5..=5);
+            if matches!(api.material_by_offset(0, -1), Air | Gas)
+                || (matches!(api.material_by_offset(0, -1), Water | Oil) && fastrand::bool())
+            {
+                api.swap_offset(0, -1)
+            } else if matches!(api.material_by_offset(x, -1), Air | Gas | Fire) {
+                api.swap_offset(x, -1);
+            } else if matches!(api.material_by_offset(x, 0), Air | Gas | Fire) {
+                api.swap_offset(x, 0);
+            } else {
+                flow_pressure(api, Acid);
+            }
+        }
+        Ice if fastrand::u8(0..4) == 0 && touching(api, CellType::hot) => {
+            api.set_cell(0, 0, Water);
+        }
+        Snow => {
+            if touching(api, CellType::hot) && fastrand::u8(0..2) == 0 {
+                api.set_cell(0, 0, Water);
+                return;
+            }
+            // Snow buried under more snow slowly packs down into ice
+            if (1..=4).all(|dy| api.material_by_offset(0, dy) == Snow) {
+                let c = api.current();
+                c.health += 1;
+                if c.health > 512 {
+                    api.set_cell(0, 0, Ice);
+                    return;
+                }
+            }
+            let x = fastrand::isize(-1..=1);
+            if matches!(api.material_by_offset(0, -1), Air | Gas | Steam) {
+                api.swap_offset(0, -1)
+            } else if api.material_by_offset(x, -1) == Air && fastrand::u8(0..4) == 0 {
+                api.swap_offset(x, -1);
+            }
+        }
+        Steam => {
+            if touching(api, CellType::cold) && fastrand::u8(0..4) == 0 {
+                api.set_cell(0, 0, Water);
+                return;
+            }
+            let dx = fastrand::isize(-9..=9) / 8;
+            let dy = fastrand::isize(-8..26) / 8;
+            match api.material_by_offset(dx, dy) {
+                Air | Gas => api.swap_offset(dx, dy),
+                // Blocked at the top, condense back into water
+                None | Stone | Metal if dy > 0 && fastrand::u8(0..64) == 0 => {
+                    api.set_cell(0, 0, Water)
+                }
+                _ => {}
+            }
+        }
+        Seed => {
+            if touching(api, |m| matches!(m, WetSand | Mud))
+                || touching(api, |m| *m == Water) && touching(api, |m| *m == Sand)
+            {
+                let height = PLANT_HEIGHT as u16 + (api.seeded_random(0) % PLANT_HEIGHT) as u16;
+                api.set_cell(0, 0, Plant);
+                api.current().lifespan = height;
+                return;
+            }
+            let x = fastrand::isize(-1..=1);
+            if matches!(api.material_by_offset(0, -1), Air | Water | Gas) {
+                api.swap_offset(0, -1)
+            } else if matches!(api.material_by_offset(x, -1), Air | Water) {
+                api.swap_offset(x, -1);
+            }
+        }
+        Plant => grow_plant(api),
+        Gunpowder => {
+            if touching(api, CellType::hot) || api.powered() {
+                detonate(api);
+                return;
+            }
+            let x = fastrand::isize(-1..=1);
+            if matches!(api.material_by_offset(0, -1), Air | Gas | Steam) {
+                api.swap_offset(0, -1)
+            } else if api.material_by_offset(x, -1) == Air {
+                api.swap_offset(x, -1);
+            }
+        }
+        Explosive if touching(api, CellType::hot) || api.powered() => detonate(api),
+        Fan => blow_fan(api),
+        Battery => {
+            for (dx, dy) in [(0, -1), (-1, 0), (1, 0), (0, 1)] {
+                api.spark(dx, dy);
             }
         }
         _ => {}
     }
 }
+
+// The parts of `simulate_steps` that reach too far to run while other threads are simulating,
+// see `CellsApi::defer`. Cells may have changed since they were deferred
+pub fn simulate_deferred(api: &mut CellsApi) {
+    match api.current_material() {
+        liquid @ (Water | Saltwater | Oil | Lava | Acid) if at_surface(api, liquid) => {
+            flow_down(api, liquid)
+        }
+        Gunpowder | Explosive => detonate(api),
+        Fan => blow_fan(api),
+        _ => {}
+    }
+}
+
+// Liquid at the top of a column pushes down on everything connected to it. Rather than tracking a
+// pressure per cell, surface cells search through the body of liquid they sit on and move to any
+// open space lower down, so connected containers level out and enclosed spaces fill.
+fn flow_pressure(api: &mut CellsApi, liquid: CellType) {
+    // Searches are long, a surface at rest would spend most of each tick on them if run more often
+    if fastrand::u8(0..32) == 0 && at_surface(api, liquid) && !api.defer() {
+        flow_down(api, liquid);
+    }
+}
+fn at_surface(api: &mut CellsApi, liquid: CellType) -> bool {
+    api.material_by_offset(0, -1) == liquid && matches!(api.material_by_offset(0, 1), Air | Gas)
+}
+fn flow_down(api: &mut CellsApi, liquid: CellType) {
+    let (mut visited, mut queue) = (
+        std::mem::take(&mut api.visited),
+        std::mem::take(&mut api.queue),
+    );
+    visited.clear();
+    queue.clear();
+    visited.insert((0, 0));
+    queue.push_back((0, 0));
+    let mut searched = 1;
+    'search: while let Some((x, y)) = queue.pop_front() {
+        for (dx, dy) in [(0, -1), (-1, 0), (1, 0), (0, 1)] {
+            let (nx, ny) = (x + dx, y + dy);
+            if !visited.insert((nx, ny)) {
+                continue;
+            }
+            let material = api.material_by_offset(nx, ny);
+            if material == Air && ny < 0 {
+                api.move_offset(nx, ny);
+                break 'search;
+            }
+            if material == liquid && searched < PRESSURE_SEARCH_LIMIT {
+                searched += 1;
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+    (api.visited, api.queue) = (visited, queue);
+}
+
+// Fans push light material in a stream in front of them, weaker further away. Anything solid in the
+// way blocks the stream
+fn blow_fan(api: &mut CellsApi) {
+    if api.defer() {
+        return;
+    }
+    let (dx, dy) = DIRECTIONS[api.current().direction as usize % DIRECTIONS.len()];
+    let (dx, dy) = api.absolute(dx, dy);
+    for side in -FAN_SPREAD..=FAN_SPREAD {
+        let lane = |distance: isize| (dx * distance - dy * side, dy * distance + dx * side);
+        let reach = (1..=FAN_RANGE)
+            .find(|distance| {
+                let (x, y) = lane(*distance);
+                api.material_by_offset(x, y).solid()
+            })
+            .unwrap_or(FAN_RANGE + 1);
+        // Work back towards the fan so cells at the front make room for those behind
+        for distance in (1..reach).rev() {
+            let (x, y) = lane(distance);
+            let (to_x, to_y) = lane(distance + 1);
+            let factor = api.material_by_offset(x, y).wind_factor();
+            let strength = 1.0 - distance as f32 / (FAN_RANGE + 1) as f32;
+            if factor > 0.0
+                && fastrand::f32() < factor * strength
+                && api.material_by_offset(to_x, to_y) == Air
+            {
+                api.swap_between(x, y, to_x, to_y);
+            }
+        }
+    }
+}
+
+// Heat drives the water out of a mixture, leaving `residue` with steam above it
+fn boil_off(api: &mut CellsApi, residue: CellType) {
+    api.set_cell(0, 0, residue);
+    if api.material_by_offset(0, 1) == Air {
+        api.set_cell(0, 1, Steam);
+    }
+}
+// Throws the surface either side of where falling water lands up into the air
+fn splash(api: &mut CellsApi, fallen: u16) {
+    let speed = (fallen as f32).sqrt() * SPLASH_SPEED;
+    for dx in [-1, 1] {
+        if matches!(api.material_by_offset(dx, -1), Water | Saltwater)
+            && api.material_by_offset(dx, 0) == Air
+        {
+            let spread = 0.5 + fastrand::f32();
+            api.launch(dx, -1, dx as f32 * spread, speed);
+        }
+    }
+}
+// Mixtures with open air above slowly lose their water without any heat
+fn dry_out(api: &mut CellsApi, residue: CellType) -> bool {
+    let drying = api.material_by_offset(0, 1) == Air && fastrand::u16(0..DRYING_CHANCE) == 0;
+    if drying {
+        api.set_cell(0, 0, residue);
+    }
+    drying
+}
+
+fn touching_count(api: &mut CellsApi, property: fn(&CellType) -> bool) -> usize {
+    let mut count = 0;
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            if (dx, dy) != (0, 0) && property(&api.material_by_offset(dx, dy)) {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+fn touching(api: &mut CellsApi, property: fn(&CellType) -> bool) -> bool {
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            if (dx, dy) != (0, 0) && property(&api.material_by_offset(dx, dy)) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+// Plants keep the water they have drunk in `health` and pass it upwards. Cells with `lifespan` left
+// are growing tips, which spend water to grow and leave stem behind them
+fn grow_tip(api: &mut CellsApi, dx: isize, budget: u16) {
+    if api.material_by_offset(dx, 1) != Air || api.current().health == 0 {
+        return;
+    }
+    let time = api.world.time;
+    api.current().health -= 1;
+    api.set_cell(dx, 1, Plant);
+    let tip = api.state_by_offset(dx, 1);
+    tip.lifespan = budget;
+    tip.updated = time;
+}
+fn grow_plant(api: &mut CellsApi) {
+    let time = api.world.time;
+    if api.current().updated == time {
+        return;
+    }
+    for (dx, dy) in [(0, -1), (-1, 0), (1, 0), (0, 1)] {
+        if api.material_by_offset(dx, dy) == Water && api.seeded_random(1) & 3 == 0 {
+            api.set_cell(dx, dy, Air);
+            let water = &mut api.current().health;
+            *water = water.saturating_add(4);
+        }
+    }
+    let water = api.current().health;
+    for dx in [0, -1, 1] {
+        if api.material_by_offset(dx, 1) != Plant {
+            continue;
+        }
+        let above = api.state_by_offset(dx, 1);
+        // Burning plants count heat in `health`, so they get no more water
+        if !above.discolored && above.health.saturating_add(1) < water {
+            above.health += 1;
+            api.current().health -= 1;
+            break;
+        }
+    }
+
+    let budget = api.current().lifespan;
+    let roll = api.seeded_random(2);
+    if budget == 0 || api.current().health == 0 || roll & 7 != 0 {
+        return;
+    }
+    let dx = match (roll >> 8) % 6 {
+        0 => -1,
+        1 => 1,
+        _ => 0,
+    };
+    // Every so often a tip splits off a smaller branch to the side
+    if budget > 6 && (roll >> 16) & 3 == 0 {
+        let side = if dx == 0 {
+            [-1, 1][(roll >> 24) as usize % 2]
+        } else {
+            -dx
+        };
+        grow_tip(api, side, budget / 2);
+    }
+    grow_tip(api, dx, budget - 1);
+    if api.material_by_offset(dx, 1) == Plant {
+        api.current().lifespan = 0;
+    }
+}
+
+// Sets off every explosive cell connected to the current one. The blast grows with the amount of
+// explosive, burns out the middle and throws loose material outward
+fn detonate(api: &mut CellsApi) {
+    if api.defer() {
+        return;
+    }
+    let mut mass = 0;
+    let (mut visited, mut queue) = (
+        std::mem::take(&mut api.visited),
+        std::mem::take(&mut api.queue),
+    );
+    visited.clear();
+    queue.clear();
+    visited.insert((0, 0));
+    queue.push_back((0, 0));
+    while let Some((x, y)) = queue.pop_front() {
+        mass += match api.material_by_offset(x, y) {
+            Explosive => 4,
+            _ => 1,
+        };
+        api.set_cell(x, y, Air);
+        for (dx, dy) in [(0, -1), (-1, 0), (1, 0), (0, 1)] {
+            let next = (x + dx, y + dy);
+            if visited.len() < EXPLOSIVE_MASS_LIMIT
+                && api.material_by_offset(next.0, next.1).explosive()
+                && visited.insert(next)
+            {
+                queue.push_back(next);
+            }
+        }
+    }
+    (api.visited, api.queue) = (visited, queue);
+    let radius = (3 + ((mass as f32).sqrt() * 1.5) as isize).min(BLAST_RADIUS_LIMIT);
+
+    // Throw loose material in a ring around the blast before the middle is cleared, the closer it
+    // is the harder it is thrown
+    let outer = radius + radius / 2;
+    for (x, y) in circle_offsets(outer) {
+        let distance_squared = x * x + y * y;
+        if distance_squared <= radius * radius {
+            continue;
+        }
+        let material = api.material_by_offset(x, y);
+        if material == Air || material.solid() {
+            continue;
+        }
+        let distance = (distance_squared as f32).sqrt();
+        let speed = (outer as f32 - distance).max(1.0) * DEBRIS_SPEED;
+        api.launch(
+            x,
+            y,
+            x as f32 / distance * speed,
+            y as f32 / distance * speed + 1.0,
+        );
+    }
+
+    for (x, y) in circle_offsets(radius) {
+        let material = api.material_by_offset(x, y);
+        let tough = matches!(material, Stone | Metal) && x * x + y * y > radius * radius / 4;
+        // Leave other explosives so the fire sets them off as a chain reaction
+        if material == None || material.explosive() || tough {
+            continue;
+        }
+        let fire = fastrand::u8(0..3) == 0;
+        api.set_cell(x, y, if fire { Fire } else { Air });
+        // Some of the flames fly out as sparks
+        if fire && fastrand::u8(0..8) == 0 {
+            let distance = ((x * x + y * y) as f32).sqrt().max(1.0);
+            let speed = fastrand::f32() * MAX_SPARK_SPEED;
+            api.launch(
+                x,
+                y,
+                x as f32 / distance * speed,
+                y as f32 / distance * speed + 1.0,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulate::{
+        api::SPARK_LENGTH,
+        boundary::{Boundaries, Boundary},
+        gravity::GravityDirection,
+        world::World,
+    };
+
+    // A small world that simulates the same way every run
+    fn world(width: usize, height: usize) -> World {
+        fastrand::seed(7);
+        let mut world = World::new(width as i32, height as i32, 1);
+        world.single_threaded = true;
+        world
+    }
+    fn fill(
+        world: &mut World,
+        xs: std::ops::Range<usize>,
+        ys: std::ops::Range<usize>,
+        material: CellType,
+    ) {
+        for y in ys {
+            for x in xs.clone() {
+                world.grid.set(y * world.width + x, Cell::new(material));
+            }
+        }
+    }
+    fn count(world: &World, material: CellType) -> usize {
+        world
+            .grid
+            .materials
+            .iter()
+            .filter(|m| **m == material)
+            .count()
+    }
+    fn run(world: &mut World, steps: u16) {
+        let mut pixels = vec![0; world.width * world.height * 4];
+        world.simulate(steps, &mut pixels);
+    }
+
+    #[test]
+    fn fire_burns_plants() {
+        let mut world = world(30, 30);
+        fill(&mut world, 10..20, 5..15, Plant);
+        // Plants full of water still burn, the water boils off first
+        for state in &mut world.grid.states {
+            state.health = 400;
+        }
+        fill(&mut world, 10..20, 15..17, Fire);
+        let before = count(&world, Plant);
+        run(&mut world, 2000);
+        assert!(
+            count(&world, Plant) < before / 4,
+            "{} of {before} plant cells left",
+            count(&world, Plant)
+        );
+    }
+
+    #[test]
+    fn sparks_travel_one_cell_per_tick() {
+        let mut world = world(20, 5);
+        let floor = world.height - 1;
+        fill(&mut world, 0..12, floor..floor + 1, Metal);
+        let wire = floor * world.width;
+        let sparked = SPARK_LENGTH + REFRACTORY_LENGTH;
+        world.grid.states[wire].charge = sparked;
+        let (mut sparking, mut resting) = (0, 0);
+        for tick in 1..=24 {
+            run(&mut world, 1);
+            for x in 0..12 {
+                // The spark reaches the cell at `x` on tick `x`, then counts down one a tick
+                let expected = match tick < x {
+                    true => 0,
+                    false => sparked.saturating_sub((tick - x) as u8),
+                };
+                let charge = world.grid.states[wire + x].charge;
+                assert_eq!(charge, expected, "cell {x} after tick {tick}");
+            }
+            match world.grid.states[wire + 6].charge {
+                0 => {}
+                charge if charge > REFRACTORY_LENGTH => sparking += 1,
+                _ => resting += 1,
+            }
+        }
+        assert_eq!(sparking, SPARK_LENGTH);
+        assert_eq!(resting, REFRACTORY_LENGTH);
+    }
+
+    #[test]
+    fn acid_eats_plants_without_watering_them() {
+        let mut world = world(30, 30);
+        fill(&mut world, 10..20, 15..25, Plant);
+        fill(&mut world, 10..20, 10..15, Acid);
+        let before = count(&world, Plant);
+        run(&mut world, 300);
+        let plants = (0..world.grid.len()).filter(|i| world.grid.materials[*i] == Plant);
+        for index in plants {
+            let health = world.grid.states[index].health;
+            assert_eq!(health, 0, "plant at {index} was watered");
+        }
+        assert!(count(&world, Plant) < before, "acid ate no plants");
+    }
+
+    #[test]
+    fn cells_decay_into_their_target_within_their_lifespan() {
+        for material in CellType::ALL {
+            let Some((min, max, target)) = material.decay() else {
+                continue;
+            };
+            for trial in 0..32 {
+                // A single cell wrapped around onto itself, so it has nothing to react with
+                let mut world = world(1, 1);
+                fastrand::seed(trial);
+                world.boundaries = Boundaries {
+                    top: Boundary::Wrap,
+                    bottom: Boundary::Wrap,
+                    left: Boundary::Wrap,
+                    right: Boundary::Wrap,
+                };
+                fill(&mut world, 0..1, 0..1, material);
+                let mut lived = 0;
+                while world.grid.material(0) == material {
+                    run(&mut world, 1);
+                    lived += 1;
+                }
+                // The tick it turns is the one after its lifespan runs out. `updated` wraps with
+                // `time`, so every 256 ticks a cell looks already updated and waits an extra tick
+                lived -= 1;
+                let late = lived / 256;
+                let decayed = world.grid.material(0);
+                assert_eq!(decayed, target, "{material:?} decayed wrongly");
+                assert!(
+                    (min..=max).contains(&(lived - late)),
+                    "{material:?} lived {lived} ticks, not {min}..={max}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sand_turns_damp_or_muddy_with_water() {
+        // Water only on top dampens the sand first
+        let mut damp = world(20, 20);
+        fill(&mut damp, 0..20, 14..20, Sand);
+        fill(&mut damp, 0..20, 13..14, Water);
+        while count(&damp, Sand) == 120 {
+            run(&mut damp, 1);
+        }
+        assert!(count(&damp, WetSand) > 0);
+        assert_eq!(count(&damp, Mud), 0);
+
+        // A grain sunk in water soaks right through
+        let mut soaked = world(20, 20);
+        fill(&mut soaked, 0..20, 10..20, Water);
+        fill(&mut soaked, 10..11, 19..20, Sand);
+        while count(&soaked, Sand) == 1 {
+            run(&mut soaked, 1);
+        }
+        assert_eq!(count(&soaked, WetSand), 0);
+        assert_eq!(count(&soaked, Mud), 1);
+    }
+
+    #[test]
+    fn salt_dissolves_and_boils_back_out() {
+        let mut dissolved = world(20, 20);
+        fill(&mut dissolved, 0..20, 8..20, Water);
+        fill(&mut dissolved, 8..12, 10..12, Salt);
+        run(&mut dissolved, 300);
+        assert_eq!(count(&dissolved, Salt), 0);
+        assert!(count(&dissolved, Saltwater) > 0);
+
+        // Boiled dry, the salt is left behind
+        let mut boiled = world(20, 20);
+        fill(&mut boiled, 0..20, 16..20, Saltwater);
+        fill(&mut boiled, 0..20, 19..20, Lava);
+        run(&mut boiled, 2000);
+        assert!(count(&boiled, Saltwater) < 80);
+        assert!(count(&boiled, Salt) > 0);
+    }
+
+    #[test]
+    fn mixtures_dry_out_in_the_open() {
+        let mut world = world(20, 5);
+        fill(&mut world, 0..20, 4..5, Mud);
+        run(&mut world, 20000);
+        assert_eq!(count(&world, Mud) + count(&world, WetSand), 0);
+        assert_eq!(count(&world, Sand), 20);
+    }
+
+    #[test]
+    fn falling_water_splashes() {
+        let mut dropped = world(30, 60);
+        fill(&mut dropped, 0..30, 50..60, Water);
+        fill(&mut dropped, 14..16, 5..7, Water);
+        let mut splashed = false;
+        for _ in 0..80 {
+            run(&mut dropped, 1);
+            splashed |= !dropped.particles.is_empty();
+        }
+        assert!(splashed, "water landed without a splash");
+
+        // Poured straight onto the surface it only ripples
+        let mut poured = world(30, 60);
+        fill(&mut poured, 0..30, 50..60, Water);
+        fill(&mut poured, 14..16, 48..50, Water);
+        for _ in 0..80 {
+            run(&mut poured, 1);
+            assert!(poured.particles.is_empty());
+        }
+    }
+
+    #[test]
+    fn sand_falls_whichever_way_gravity_points() {
+        for (direction, landed) in [
+            (GravityDirection::Down, (4, 8)),
+            (GravityDirection::Left, (0, 4)),
+            (GravityDirection::Up, (4, 0)),
+            (GravityDirection::Right, (8, 4)),
+            (GravityDirection::Zero, (4, 4)),
+        ] {
+            let mut world = world(9, 9);
+            world.gravity.direction = direction;
+            fill(&mut world, 4..5, 4..5, Sand);
+            run(&mut world, 30);
+            let index = world.grid.materials.iter().position(|m| *m == Sand);
+            let position = index.map(|index| (index % world.width, index / world.width));
+            assert_eq!(position, Some(landed), "falling {direction:?}");
+        }
+    }
+
+    #[test]
+    fn void_edges_delete_what_falls_through() {
+        let mut world = world(10, 10);
+        world.boundaries.bottom = Boundary::Void;
+        fill(&mut world, 0..10, 2..4, Sand);
+        fill(&mut world, 0..10, 5..6, Water);
+        run(&mut world, 60);
+        assert_eq!(count(&world, Sand) + count(&world, Water), 0);
+    }
+
+    #[test]
+    fn wrapping_edges_move_cells_across() {
+        let mut world = world(10, 10);
+        world.boundaries.top = Boundary::Wrap;
+        world.boundaries.bottom = Boundary::Wrap;
+        fill(&mut world, 4..5, 9..10, Sand);
+        run(&mut world, 1);
+        let index = world.grid.materials.iter().position(|m| *m == Sand);
+        assert_eq!(index, Some(4), "sand didn't wrap to the top row");
+    }
+
+    #[test]
+    fn emitters_produce_at_their_rate() {
+        for rate in [0, 4, 8, Cell::MAX_RATE] {
+            let mut world = world(30, 60);
+            world.place_emitter(15, 2, Sand, rate);
+            run(&mut world, 400);
+            // One try a tick at `rate` in `MAX_RATE`, and a few land on sand already piled on top
+            let tries = 400 * rate as usize / Cell::MAX_RATE as usize;
+            let sand = count(&world, Sand);
+            assert!(
+                (tries * 3 / 4..=tries * 5 / 4).contains(&sand),
+                "rate {rate} made {sand} sand, expected about {tries}"
+            );
+        }
+    }
+
+    #[test]
+    fn drains_remove_what_touches_them() {
+        let mut world = world(10, 10);
+        fill(&mut world, 0..10, 2..6, Water);
+        for x in 0..10 {
+            world.place_drain(x, 9);
+        }
+        // Solids touching a drain are left alone
+        fill(&mut world, 0..1, 8..9, Stone);
+        run(&mut world, 200);
+        assert_eq!(count(&world, Water), 0);
+        assert_eq!(count(&world, Stone), 1);
+    }
+
+    #[test]
+    fn u_tubes_level_out() {
+        let mut world = world(40, 40);
+        // Thick stone walls, a top bar and a divider hanging from it, all one anchored piece
+        fill(&mut world, 0..5, 0..40, Stone);
+        fill(&mut world, 35..40, 0..40, Stone);
+        fill(&mut world, 0..40, 0..3, Stone);
+        fill(&mut world, 0..40, 35..40, Stone);
+        fill(&mut world, 17..23, 3..30, Stone);
+        // The left arm is full, the right one only has what is in the channel between them
+        fill(&mut world, 5..17, 10..35, Water);
+        fill(&mut world, 17..35, 30..35, Water);
+        let height = |world: &World, arm: std::ops::Range<usize>| {
+            let width = arm.len() as f32;
+            let water = (3..30)
+                .flat_map(|y| arm.clone().map(move |x| y * 40 + x))
+                .filter(|index| world.grid.material(*index) == Water)
+                .count();
+            water as f32 / width
+        };
+        run(&mut world, 1500);
+        let (left, right) = (height(&world, 5..17), height(&world, 23..35));
+        assert!(
+            (left - right).abs() <= 1.0,
+            "the arms are {left} and {right} rows deep"
+        );
+    }
+
+    #[test]
+    fn blasts_clear_a_circle_and_spare_tough_walls() {
+        // 16 high explosive cells weigh 64, which makes a blast radius of 3 + 1.5 * 8
+        let radius: isize = 15;
+        let (x, y) = (30, 30);
+        // Loose sand is cleared out to the radius and thrown from a ring half as wide again. Stone
+        // is tough, only the inner half of the blast breaks it and nothing is thrown. As squares
+        // of distances, like the blast measures them
+        let thrown = radius + radius / 2;
+        for (material, cleared, reach) in [
+            (Sand, radius * radius, thrown * thrown),
+            (Stone, radius * radius / 4, radius * radius / 4),
+        ] {
+            let mut world = world(61, 61);
+            // Without gravity nothing falls into the crater before it can be checked
+            world.gravity.direction = GravityDirection::Zero;
+            fill(&mut world, 0..61, 0..61, material);
+            // Lava only touches the corner of the block, so that is where it goes off from
+            fill(&mut world, x..x + 4, y..y + 4, Explosive);
+            fill(&mut world, x - 1..x, y - 1..y, Lava);
+            run(&mut world, 1);
+            assert_eq!(count(&world, Explosive), 0);
+            for index in 0..world.grid.len() {
+                let dx = (index % world.width) as isize - x as isize;
+                let dy = (index / world.width) as isize - y as isize;
+                let distance_squared = dx * dx + dy * dy;
+                let left = world.grid.material(index) == material;
+                if distance_squared <= cleared {
+                    assert!(!left, "{material:?} at ({dx}, {dy}) survived");
+                }
+                if distance_squared > reach {
+                    assert!(left, "{material:?} at ({dx}, {dy}) was destroyed");
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,12 @@
 pub mod api;
+pub mod bodies;
+pub mod boundary;
 pub mod cells;
+pub mod debug;
+pub mod gravity;
+pub mod grid;
+pub mod lighting;
 pub mod logic;
+pub mod particles;
+pub mod save;
 pub mod world;
@@ -0,0 +1,107 @@
+use super::{boundary::Boundary, cells::Cell, world::World};
+
+const GRAVITY: f32 = 0.15;
+// Fraction of its speed a particle keeps each tick
+const DRAG: f32 = 0.98;
+const MAX_SPEED: f32 = 6.0;
+// Particles slower than this drop back into the grid where they are, so nothing floats forever
+const SETTLE_SPEED: f32 = 0.05;
+// How far above its landing spot a particle can end up when something else got there first
+const MAX_PILE_HEIGHT: isize = 32;
+
+// A cell knocked out of the grid. It flies freely until it hits something, then lands back in
+// the grid as the cell it was
+#[derive(Clone, Copy)]
+pub struct Particle {
+    pub cell: Cell,
+    // Position and velocity in cells, y points down like the grid
+    pub x: f32,
+    pub y: f32,
+    pub vx: f32,
+    pub vy: f32,
+}
+
+impl World {
+    pub fn step_particles(&mut self, steps: u16, pixels: &mut [u8]) {
+        if self.particles.is_empty() {
+            return;
+        }
+        let (down_x, down_y) = self.gravity.direction.down();
+        let pull = GRAVITY * self.gravity.effective_strength();
+        let mut particles = std::mem::take(&mut self.particles);
+        for _ in 0..steps {
+            particles.retain_mut(|particle| {
+                particle.vx =
+                    ((particle.vx + down_x as f32 * pull) * DRAG).clamp(-MAX_SPEED, MAX_SPEED);
+                particle.vy =
+                    ((particle.vy + down_y as f32 * pull) * DRAG).clamp(-MAX_SPEED, MAX_SPEED);
+                if particle.vx.abs().max(particle.vy.abs()) < SETTLE_SPEED {
+                    self.land(particle, pixels);
+                    return false;
+                }
+                self.fly(particle, pixels)
+            });
+        }
+        self.particles = particles;
+    }
+    // Moves a particle along its velocity no more than a cell at a time, so it can't skip through
+    // thin walls. False once it has landed or left the world
+    fn fly(&mut self, particle: &mut Particle, pixels: &mut [u8]) -> bool {
+        let cells = particle.vx.abs().max(particle.vy.abs()).ceil().max(1.0);
+        let (step_x, step_y) = (particle.vx / cells, particle.vy / cells);
+        for _ in 0..cells as usize {
+            let (x, y) = (particle.x + step_x, particle.y + step_y);
+            let located = self.boundaries.locate(
+                x.floor() as isize,
+                y.floor() as isize,
+                self.width,
+                self.height,
+            );
+            match located {
+                Ok((cell_x, cell_y)) if self.material(cell_y * self.width + cell_x).passable() => {
+                    // Keeps the position inside the grid after crossing a wrapping edge
+                    particle.x = cell_x as f32 + (x - x.floor());
+                    particle.y = cell_y as f32 + (y - y.floor());
+                }
+                Err(Boundary::Void) => return false,
+                _ => {
+                    self.land(particle, pixels);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+    // Puts a particle back into the grid at its position, or piled on top of whatever has landed
+    // there first. If there is no room nearby the particle is lost
+    fn land(&mut self, particle: &Particle, pixels: &mut [u8]) {
+        let (down_x, down_y) = self.gravity.direction.down();
+        let (x, y) = (particle.x as isize, particle.y as isize);
+        for height in 0..=MAX_PILE_HEIGHT {
+            let located = self.boundaries.locate(
+                x - down_x * height,
+                y - down_y * height,
+                self.width,
+                self.height,
+            );
+            let Ok((x, y)) = located else {
+                return;
+            };
+            let index = y * self.width + x;
+            if self.material(index).passable() {
+                if particle.cell.material.rigid() {
+                    self.bodies_dirty = true;
+                }
+                self.set_pixel(index, particle.cell, pixels);
+                return;
+            }
+        }
+    }
+    // Particles are drawn over the grid without changing it
+    pub fn draw_particles(&self, pixels: &mut [u8]) {
+        for particle in &self.particles {
+            let index = particle.y as usize * self.width + particle.x as usize;
+            pixels[index * 4..index * 4 + 3].copy_from_slice(&particle.cell.rgb);
+        }
+    }
+}
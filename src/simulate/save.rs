@@ -0,0 +1,340 @@
+use std::fmt;
+
+use crate::gui::input::CenterLocation;
+
+use super::{
+    cells::{Cell, CellType},
+    grid::Grid,
+    world::World,
+};
+
+// Binary snapshot of a world: header followed by one fixed size record per cell
+const MAGIC: &[u8; 4] = b"SNAD";
+const VERSION: u8 = 4;
+const HEADER_SIZE: usize = 13;
+const CELL_SIZE: usize = 14;
+
+// Each version added fields to the end of the cell record, so older saves still load with the
+// fields they don't have starting out like a new cell's
+fn record_size(version: u8) -> Option<usize> {
+    match version {
+        1 => Some(10),
+        // Spark charge
+        2 => Some(11),
+        // Fan direction
+        3 => Some(12),
+        // Emitted material and rate
+        VERSION => Some(CELL_SIZE),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    UnknownMaterial(u8),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::BadMagic => write!(f, "Not a snad stack save"),
+            SaveError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported save version {}", version)
+            }
+            SaveError::Truncated => write!(f, "Save data ended early"),
+            SaveError::UnknownMaterial(id) => write!(f, "Unknown material id {}", id),
+        }
+    }
+}
+
+fn encode_cell(cell: &Cell, out: &mut Vec<u8>) {
+    out.push(cell.material as u8);
+    out.extend_from_slice(&cell.rgb);
+    out.push(cell.state.selected as u8 | (cell.state.discolored as u8) << 1);
+    out.push(cell.state.updated);
+    out.extend_from_slice(&cell.state.health.to_le_bytes());
+    out.extend_from_slice(&cell.state.lifespan.to_le_bytes());
+    out.push(cell.state.charge);
+    out.push(cell.state.direction);
+    out.push(cell.state.emits);
+    out.push(cell.state.rate);
+}
+
+fn decode_material(id: u8) -> Result<CellType, SaveError> {
+    CellType::ALL
+        .get(id as usize)
+        .copied()
+        .ok_or(SaveError::UnknownMaterial(id))
+}
+
+// Checks the header of a save or diff, returning the size of the world it came from
+pub fn read_header(bytes: &[u8]) -> Result<(usize, usize), SaveError> {
+    header(bytes).map(|(width, height, _)| (width, height))
+}
+// Also returns the size of the cell records that follow
+fn header(bytes: &[u8]) -> Result<(usize, usize, usize), SaveError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(SaveError::Truncated);
+    }
+    if &bytes[0..4] != MAGIC {
+        return Err(SaveError::BadMagic);
+    }
+    let record_size = record_size(bytes[4]).ok_or(SaveError::UnsupportedVersion(bytes[4]))?;
+    let width = u32::from_le_bytes(bytes[5..9].try_into().unwrap()) as usize;
+    let height = u32::from_le_bytes(bytes[9..13].try_into().unwrap()) as usize;
+    Ok((width, height, record_size))
+}
+// Checks a whole save, returning its size, record size and cell records
+fn records(bytes: &[u8]) -> Result<(usize, usize, usize, &[u8]), SaveError> {
+    let (width, height, record_size) = header(bytes)?;
+    let records = &bytes[HEADER_SIZE..];
+    // Sizes come from the file, so a header too big to multiply out can't have come with its cells
+    let size = width
+        .checked_mul(height)
+        .and_then(|cells| cells.checked_mul(record_size))
+        .ok_or(SaveError::Truncated)?;
+    if records.len() < size {
+        return Err(SaveError::Truncated);
+    }
+    Ok((width, height, record_size, records))
+}
+
+// Fills in how many records the run starting at `start` ended up with
+fn end_run(out: &mut [u8], start: usize) {
+    let count = ((out.len() - start - 4) / CELL_SIZE) as u32;
+    out[start..start + 4].copy_from_slice(&count.to_le_bytes());
+}
+
+fn decode_cell(bytes: &[u8]) -> Result<Cell, SaveError> {
+    let mut cell = Cell::new(decode_material(bytes[0])?);
+    cell.rgb.copy_from_slice(&bytes[1..4]);
+    cell.state.selected = bytes[4] & 1 != 0;
+    cell.state.discolored = bytes[4] & 2 != 0;
+    cell.state.updated = bytes[5];
+    cell.state.health = u16::from_le_bytes([bytes[6], bytes[7]]);
+    cell.state.lifespan = u16::from_le_bytes([bytes[8], bytes[9]]);
+    if let Some(&charge) = bytes.get(10) {
+        cell.state.charge = charge;
+    }
+    if let Some(&direction) = bytes.get(11) {
+        cell.state.direction = direction;
+    }
+    if let [emits, rate] = bytes[12.min(bytes.len())..] {
+        cell.state.emits = decode_material(emits)? as u8;
+        cell.state.rate = rate;
+    }
+    Ok(cell)
+}
+
+impl World {
+    pub fn save(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.save_size());
+        self.write_header(&mut out);
+        for cell in self.grid.iter() {
+            encode_cell(&cell, &mut out);
+        }
+        out
+    }
+    pub fn save_size(&self) -> usize {
+        HEADER_SIZE + self.grid.len() * CELL_SIZE
+    }
+    fn write_header(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&(self.width as u32).to_le_bytes());
+        out.extend_from_slice(&(self.height as u32).to_le_bytes());
+    }
+    // Cells that differ from an earlier copy of the grid, None if it was a different size. After
+    // the save header come runs of unchanged cells to skip, each followed by a run of new cell
+    // records, so a world where little is moving makes a small diff
+    pub fn diff(&self, previous: &Grid) -> Option<Vec<u8>> {
+        if previous.len() != self.grid.len() {
+            return None;
+        }
+        let mut out = vec![];
+        self.write_header(&mut out);
+        let mut skipped = 0u32;
+        // Where the count of the run of changed cells being written goes
+        let mut run = None;
+        for index in 0..self.grid.len() {
+            if !self.grid.differs(previous, index) {
+                if let Some(start) = run.take() {
+                    end_run(&mut out, start);
+                }
+                skipped += 1;
+                continue;
+            }
+            if run.is_none() {
+                out.extend_from_slice(&skipped.to_le_bytes());
+                run = Some(out.len());
+                out.extend_from_slice(&[0; 4]);
+                skipped = 0;
+            }
+            encode_cell(&self.grid.get(index), &mut out);
+        }
+        if let Some(start) = run {
+            end_run(&mut out, start);
+        }
+        Some(out)
+    }
+    // Loads a save into the current grid. Saves of a different size are anchored to the top left
+    pub fn load(&mut self, bytes: &[u8]) -> Result<(), SaveError> {
+        let (width, height, record_size, records) = records(bytes)?;
+        let mut grid = Grid::new(self.width * self.height, Cell::new(CellType::Air));
+        for y in 0..height.min(self.height) {
+            for x in 0..width.min(self.width) {
+                let start = (y * width + x) * record_size;
+                grid.set(
+                    y * self.width + x,
+                    decode_cell(&records[start..start + record_size])?,
+                );
+            }
+        }
+        self.grid = grid;
+        self.grid_replaced();
+        Ok(())
+    }
+    // Loads a save at the size it was made, resizing the world to match
+    pub fn load_resized(&mut self, bytes: &[u8]) -> Result<(), SaveError> {
+        let (width, height, _, _) = records(bytes)?;
+        if (width, height) != (self.width, self.height) {
+            self.resize(width, height, CenterLocation::new());
+        }
+        self.load(bytes)
+    }
+    // Applies a `World::diff` on top of the current grid, anchored to the top left like `load`
+    pub fn load_diff(&mut self, bytes: &[u8]) -> Result<(), SaveError> {
+        let (width, _, record_size) = header(bytes)?;
+        let mut runs = &bytes[HEADER_SIZE..];
+        let mut index = 0usize;
+        while !runs.is_empty() {
+            if runs.len() < 8 {
+                return Err(SaveError::Truncated);
+            }
+            let skipped = u32::from_le_bytes(runs[0..4].try_into().unwrap()) as usize;
+            let count = u32::from_le_bytes(runs[4..8].try_into().unwrap()) as usize;
+            let end = count
+                .checked_mul(record_size)
+                .and_then(|size| size.checked_add(8))
+                .ok_or(SaveError::Truncated)?;
+            let records = runs.get(8..end).ok_or(SaveError::Truncated)?;
+            index = index.saturating_add(skipped);
+            for record in records.chunks_exact(record_size) {
+                let (x, y) = (index % width.max(1), index / width.max(1));
+                if x < self.width && y < self.height {
+                    let cell = decode_cell(record)?;
+                    if cell.material.rigid() {
+                        self.bodies_dirty = true;
+                    }
+                    self.grid.set(y * self.width + x, cell);
+                }
+                index = index.saturating_add(1);
+            }
+            runs = &runs[8 + records.len()..];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two cells in a given version's records: a fan with every field set, then plain sand. Each
+    // version's record is a prefix of the next
+    fn fixture(version: u8) -> Vec<u8> {
+        let mut bytes = b"SNAD".to_vec();
+        bytes.push(version);
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        // Material, colour, flags and update stamp, health, lifespan, then charge, direction, and
+        // emitted material with its rate
+        let fan = [
+            &[CellType::Fan as u8, 1, 2, 3, 0b11, 5][..],
+            &0x1234u16.to_le_bytes(),
+            &0x5678u16.to_le_bytes(),
+            &[9, 3, CellType::Water as u8, 7],
+        ]
+        .concat();
+        let sand = [
+            &[CellType::Sand as u8, 10, 20, 30][..],
+            &[0; 8],
+            &[CellType::Air as u8, 0],
+        ]
+        .concat();
+        let size = record_size(version).unwrap();
+        bytes.extend_from_slice(&fan[..size]);
+        bytes.extend_from_slice(&sand[..size]);
+        bytes
+    }
+
+    #[test]
+    fn older_saves_load_with_new_fields_defaulted() {
+        for version in 1..=3 {
+            let mut world = World::new(1, 1, 1);
+            world.load_resized(&fixture(version)).unwrap();
+            assert_eq!((world.width, world.height), (2, 1));
+
+            let fan = world.grid.get(0);
+            assert_eq!(fan.material, CellType::Fan);
+            assert_eq!(fan.rgb, [1, 2, 3]);
+            let state = fan.state;
+            assert!(state.selected && state.discolored);
+            assert_eq!(
+                (state.updated, state.health, state.lifespan),
+                (5, 0x1234, 0x5678)
+            );
+            let charge = if version >= 2 { 9 } else { 0 };
+            let direction = if version >= 3 { 3 } else { 0 };
+            assert_eq!((state.charge, state.direction), (charge, direction));
+            assert_eq!((state.emitted(), state.rate), (CellType::Air, 0));
+
+            let sand = world.grid.get(1);
+            assert_eq!((sand.material, sand.rgb), (CellType::Sand, [10, 20, 30]));
+        }
+    }
+
+    #[test]
+    fn saves_and_diffs_load_back_the_same_grid() {
+        let mut world = World::new(5, 4, 1);
+        world.grid.set(3, Cell::emitter(CellType::Water, 7));
+        let mut fan = Cell::new(CellType::Fan);
+        fan.state.direction = 5;
+        fan.state.selected = true;
+        world.grid.set(7, fan);
+        let mut metal = Cell::new(CellType::Metal);
+        metal.state.charge = 4;
+        metal.state.health = 300;
+        world.grid.set(12, metal);
+
+        let mut loaded = World::new(1, 1, 1);
+        loaded.load_resized(&world.save()).unwrap();
+        assert_eq!(loaded.save(), world.save());
+
+        let previous = world.grid.clone();
+        world.grid.set(0, Cell::new(CellType::Steam));
+        world.grid.set(12, Cell::new(CellType::Air));
+        world.grid.set(19, Cell::new(CellType::Lava));
+        let diff = world.diff(&previous).unwrap();
+        assert!(diff.len() < world.save_size());
+        loaded.load_diff(&diff).unwrap();
+        assert_eq!(loaded.save(), world.save());
+    }
+
+    #[test]
+    fn broken_saves_are_refused() {
+        let mut world = World::new(2, 1, 1);
+        let mut bytes = fixture(VERSION);
+        assert!(world.load(&bytes[..bytes.len() - 1]).is_err());
+        bytes[4] = VERSION + 1;
+        assert!(matches!(
+            world.load(&bytes),
+            Err(SaveError::UnsupportedVersion(_))
+        ));
+        bytes[0] = b'X';
+        assert!(matches!(world.load(&bytes), Err(SaveError::BadMagic)));
+    }
+}
@@ -2,51 +2,127 @@ use crate::{
     gui::input::CenterLocation,
     simulate::{
         api::{CellsApi, SharedCellApi, UnsafeShared},
-        cells::{Cell, CellType},
+        bodies::RigidBody,
+        boundary::Boundaries,
+        cells::{Cell, CellState, CellType},
+        debug::DebugView,
+        gravity::Gravity,
+        grid::Grid,
+        lighting::Lighting,
+        particles::Particle,
     },
 };
-use std::{
-    ops::{Index, IndexMut},
-    sync::Arc,
-    thread,
-};
+use std::{sync::Arc, thread};
 
 pub struct World {
-    pub grid: Vec<Cell>,
+    pub grid: Grid,
     pub density: u32,
     pub width: usize,
     pub height: usize,
     pub time: u8,
+    // Total steps simulated, unlike `time` this never wraps
+    pub ticks: u64,
+    // Anything that has to play out the same way every time hashes this instead of using fastrand
+    pub seed: u64,
+    pub debug_view: DebugView,
+    // Column ranges each thread simulated during the last call to `simulate`
+    pub thread_strips: Vec<(usize, usize)>,
+    pub lighting: Lighting,
+    pub gravity: Gravity,
+    pub boundaries: Boundaries,
+    // Steady push on light materials, in cells per tick with y pointing up
+    pub wind: (f32, f32),
+    // Direction newly placed fans face
+    pub fan_direction: u8,
+    // What newly placed emitters produce, and how often
+    pub emitter_material: CellType,
+    pub emitter_rate: u8,
+    pub bodies: Vec<RigidBody>,
+    // Cells flying freely above the grid
+    pub particles: Vec<Particle>,
+    // Set when rigid cells were placed or removed, so unsupported groups are looked for again
+    pub bodies_dirty: bool,
+    // Rigid cells the last look for bodies found. While there were none and none were placed since,
+    // nothing can break off and the grid isn't searched
+    pub rigid_cells: usize,
+    // Keeps simulation on one thread, slower but the same every time for the same seed and inputs
+    pub single_threaded: bool,
 }
 impl World {
+    // Most cells a world can have. Sizes come from the command line, recordings and servers, so
+    // anything far bigger than a screen is refused rather than allocated
+    pub const MAX_CELLS: usize = 1 << 24;
+    pub fn valid_size(width: usize, height: usize) -> bool {
+        width > 0
+            && height > 0
+            && width
+                .checked_mul(height)
+                .is_some_and(|cells| cells <= Self::MAX_CELLS)
+    }
     pub fn new(width: i32, height: i32, density: u32) -> Self {
         let height = (height as usize) / density as usize;
         let width = (width as usize) / density as usize;
-        let grid = vec![Cell::new(CellType::Air); width * height];
+        let grid = Grid::new(width * height, Cell::new(CellType::Air));
         Self {
             grid,
             density,
             width,
             height,
             time: 0,
+            ticks: 0,
+            seed: fastrand::u64(..),
+            debug_view: DebugView::Normal,
+            thread_strips: vec![(0, width)],
+            lighting: Lighting::default(),
+            gravity: Gravity::default(),
+            boundaries: Boundaries::default(),
+            wind: (0.0, 0.0),
+            fan_direction: 0,
+            emitter_material: CellType::Water,
+            emitter_rate: 4,
+            bodies: vec![],
+            particles: vec![],
+            bodies_dirty: false,
+            rigid_cells: 0,
+            single_threaded: false,
         }
     }
-    pub fn resize(&mut self, width: usize, height: usize, offsets: CenterLocation) {
-        let mut new_grid = vec![Cell::new(CellType::Air); width * height];
+    // Bodies and particles in flight belong to the old grid once it is swapped out
+    pub fn grid_replaced(&mut self) {
+        self.reset_bodies();
+        self.particles.clear();
+    }
+    // Lookups by grid index, which the simulation makes so many of they skip bounds checks. Callers
+    // have to keep indexes inside the grid, code outside the crate goes through `Grid` instead
+    #[inline]
+    pub(crate) fn material(&self, index: usize) -> CellType {
+        debug_assert!(index < self.grid.len(), "{index} is outside the grid");
+        unsafe { *self.grid.materials.get_unchecked(index) }
+    }
+    #[inline]
+    pub(crate) fn state_mut(&mut self, index: usize) -> &mut CellState {
+        debug_assert!(index < self.grid.len(), "{index} is outside the grid");
+        unsafe { self.grid.states.get_unchecked_mut(index) }
+    }
+    pub fn clear(&mut self) {
+        self.grid.fill(Cell::new(CellType::Air));
+        self.grid_replaced();
+    }
+    pub fn resize(&mut self, width: usize, height: usize, _offsets: CenterLocation) {
+        let mut new_grid = Grid::new(width * height, Cell::new(CellType::Air));
         // TODO: smarter resize
-        self.grid
-            .chunks_exact(self.width)
-            .zip(new_grid.chunks_exact_mut(width))
-            .for_each(|(old, new)| {
-                for (new_element, old_element) in new.iter_mut().zip(old.iter()) {
-                    *new_element = *old_element;
-                }
-            });
+        for y in 0..height.min(self.height) {
+            for x in 0..width.min(self.width) {
+                new_grid.set(y * width + x, self.grid.get(y * self.width + x));
+            }
+        }
         self.grid = new_grid;
         self.width = width;
         self.height = height;
+        self.grid_replaced();
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn draw_thick_line(
         &mut self,
         x1: i32,
@@ -64,6 +140,20 @@ impl World {
                 .chain(std::iter::once((x2 as isize, y2 as isize))) // Manually add endpoint
                 .collect::<Vec<(isize, isize)>>();
 
+        // Cells the stroke covers, marked first so each is placed once. The ring is swept along
+        // every point of the line, placing it each time made most of the work overwriting cells
+        let clamp_x = |x: isize| x.clamp(0, self.width as isize - 1) as usize;
+        let clamp_y = |y: isize| y.clamp(0, self.height as isize - 1) as usize;
+        let (left, right) = (x1.min(x2) as isize, x1.max(x2) as isize);
+        let (top, bottom) = (y1.min(y2) as isize, y1.max(y2) as isize);
+        let (left, right) = (clamp_x(left - radius), clamp_x(right + radius));
+        let (top, bottom) = (clamp_y(top - radius), clamp_y(bottom + radius));
+        let stride = right - left + 1;
+        let mut covered = vec![false; stride * (bottom - top + 1)];
+        let mut cover = |x: isize, y: isize| {
+            covered[(clamp_y(y) - top) * stride + clamp_x(x) - left] = true;
+        };
+
         let diameter = radius * 2;
         for index_y in 0..diameter {
             for index_x in 0..diameter {
@@ -79,27 +169,42 @@ impl World {
 
                 if (distance_non_sqrt as f64).sqrt() + 1.5 >= radius as f64 && corner {
                     for point in &line {
-                        let x = (point.0 + index_x - radius)
-                            .clamp(0, self.width as isize - 1)
-                            as usize;
-
-                        let y = (point.1 + index_y - radius)
-                            .clamp(0, self.height as isize - 1)
-                            as usize;
-
-                        self.place_tile(x, y, material, pixels, hover, place)
+                        cover(point.0 + index_x - radius, point.1 + index_y - radius);
                     }
                 } else {
-                    let x =
-                        (x2 as isize + index_x - radius).clamp(0, self.width as isize - 1) as usize;
-                    let y = (y2 as isize + index_y - radius).clamp(0, self.height as isize - 1)
-                        as usize;
-
-                    self.place_tile(x, y, material, pixels, hover, place)
+                    cover(
+                        x2 as isize + index_x - radius,
+                        y2 as isize + index_y - radius,
+                    );
+                }
+            }
+        }
+        for y in top..=bottom {
+            for x in left..=right {
+                if covered[(y - top) * stride + x - left] {
+                    self.place_tile(x, y, material, pixels, hover, place);
                 }
             }
         }
     }
+    // Single cell placement for building worlds in code, the pixel is redrawn on the next render
+    pub fn place_emitter(&mut self, x: usize, y: usize, emits: CellType, rate: u8) {
+        self.put_cell(x, y, Cell::emitter(emits, rate));
+    }
+    pub fn place_drain(&mut self, x: usize, y: usize) {
+        self.put_cell(x, y, Cell::new(CellType::Drain));
+    }
+    fn put_cell(&mut self, x: usize, y: usize, cell: Cell) {
+        assert!(
+            x < self.width && y < self.height,
+            "({x}, {y}) is outside the world"
+        );
+        let index = y * self.width + x;
+        if self.material(index).rigid() {
+            self.bodies_dirty = true;
+        }
+        self.grid.set(index, cell);
+    }
     fn place_tile(
         &mut self,
         x: usize,
@@ -110,10 +215,19 @@ impl World {
         place: bool,
     ) {
         let index = y * self.width + x;
-        let cell = &mut self[index];
+        if place && (self.material(index).rigid() || material.rigid()) {
+            self.bodies_dirty = true;
+        }
         unsafe {
             if place {
-                *cell = Cell::new(material);
+                let mut cell = match material {
+                    CellType::Emitter => Cell::emitter(self.emitter_material, self.emitter_rate),
+                    _ => Cell::new(material),
+                };
+                if material == CellType::Fan {
+                    cell.state.direction = self.fan_direction;
+                }
+                self.grid.set(index, cell);
                 pixels
                     .get_unchecked_mut(index * 4..index * 4 + 3)
                     .copy_from_slice(&cell.rgb);
@@ -128,32 +242,57 @@ impl World {
     pub fn simulate(&mut self, steps: u16, pixels: &mut [u8]) {
         let width = self.width; // Will complain about use after borrow without this
         let left_edge_random = fastrand::usize(0..width / 16 + 1);
-        let chunk_width = width / 6;
+        let chunk_width = (width / 6).max(1);
         let parallelize_chunks = (left_edge_random..width).step_by(chunk_width);
+        // At low sizes, the overhead of managing multiple threads becomes too large.
+        // Also, wasm threads are weird, so I prefer to not deal with them. Wrapped edges would let
+        // the rightmost strip reach into the leftmost ones while they run, so wrapping worlds stay on
+        // one thread too
+        let threaded = width >= 100
+            && !cfg!(target_arch = "wasm32")
+            && !self.single_threaded
+            && !self.boundaries.wraps_horizontally();
+        self.thread_strips = if !threaded {
+            vec![(0, width)]
+        } else {
+            std::iter::once((0, left_edge_random))
+                .chain(
+                    parallelize_chunks
+                        .clone()
+                        .map(|start| (start, (start + chunk_width).min(width))),
+                )
+                .collect()
+        };
         let arc_api = Arc::new(UnsafeShared::new(SharedCellApi::new(
             self,
             pixels,
             parallelize_chunks.len(),
+            threaded,
         )));
-        // At low sizes, the overhead of managing multiple threads becomes too large.
-        // Also, wasm threads are weird, so I prefer to not deal with them
-        if width < 100 || cfg!(target_arch = "wasm32") {
+        if !threaded {
+            let mut api = CellsApi::new(arc_api.get_api());
             for _ in 0..steps {
-                let mut api = CellsApi::new(arc_api.get_api());
                 api.advance_time();
+                api.emit_sources();
                 api.simulate(0, width);
+                api.step_bodies();
             }
         } else {
-            // Main thread, in charge of advancing the time
+            // Main thread, in charge of advancing the time. It only does that once every other
+            // thread has finished the step before, as nothing else may be simulating
             let arc_api = Arc::clone(&arc_api);
             thread::scope(|s| {
                 s.spawn(|| {
+                    let mut api = CellsApi::new(arc_api.get_api());
                     for _ in 0..steps {
-                        let mut api = CellsApi::new(arc_api.get_api());
                         api.advance_time();
+                        api.emit_sources();
                         api.wait_start();
                         api.simulate(0, left_edge_random);
                         api.sync_threads();
+                        api.wait_finish();
+                        api.run_deferred();
+                        api.step_bodies();
                     }
                 });
                 for chunk_start in parallelize_chunks {
@@ -161,8 +300,8 @@ impl World {
                     let is_rightmost = chunk_end == width;
                     let arc_api = Arc::clone(&arc_api);
                     s.spawn(move || {
+                        let mut api = CellsApi::new(arc_api.get_api());
                         for _ in 0..steps {
-                            let mut api = CellsApi::new(arc_api.get_api());
                             api.wait_start();
                             if is_rightmost {
                                 api.simulate(chunk_end - 15, chunk_end);
@@ -172,48 +311,117 @@ impl World {
                                 api.sync_threads();
                                 api.simulate(chunk_start, chunk_end);
                             }
+                            api.wait_finish();
                         }
                     });
                 }
             });
         }
+        let launched = std::mem::take(arc_api.get_api().launched.get_mut().unwrap());
+        drop(arc_api);
+        self.particles.extend(launched);
+        self.step_particles(steps, pixels);
     }
     pub fn render(&mut self, pixels: &mut [u8]) {
+        if self.debug_view != DebugView::Normal {
+            self.render_debug(pixels);
+            return;
+        }
+        let (width, time) = (self.width, self.time);
+        let cells = self.grid.materials.iter().zip(&self.grid.colors);
+        for (index, ((material, rgb), pixel)) in cells.zip(pixels.chunks_exact_mut(4)).enumerate() {
+            let rgb = match material.shaded() {
+                true => self
+                    .grid
+                    .get(index)
+                    .shade(index % width, index / width, time),
+                false => *rgb,
+            };
+            pixel[0..3].copy_from_slice(&rgb);
+            pixel[3] = 255;
+        }
+        self.draw_particles(pixels);
+        if self.lighting.enabled {
+            self.apply_lighting(pixels);
+        }
+    }
+    fn render_debug(&mut self, pixels: &mut [u8]) {
+        let mut column_owner = vec![0; self.width];
+        for (strip, (start, end)) in self.thread_strips.iter().enumerate() {
+            for owner in &mut column_owner[(*start).min(self.width)..(*end).min(self.width)] {
+                *owner = strip;
+            }
+        }
         for y in 0..self.height {
-            for x in 0..self.width {
+            for (x, owner) in column_owner.iter().enumerate() {
                 let index = y * self.width + x;
-                let cell = &mut self[index];
+                let rgb = self
+                    .debug_view
+                    .color(&self.grid.get(index), self.time, *owner);
                 let pixel = &mut pixels[index * 4..index * 4 + 4];
-                pixel[0..3].copy_from_slice(&cell.rgb);
+                pixel[0..3].copy_from_slice(&rgb);
                 pixel[3] = 255;
             }
         }
     }
 }
-impl Index<(usize, usize)> for World {
-    type Output = Cell;
-    fn index(&self, index: (usize, usize)) -> &Self::Output {
-        let (y, x) = index;
-        unsafe { self.grid.get_unchecked(y * self.width + x) }
-    }
-}
 
-impl IndexMut<(usize, usize)> for World {
-    fn index_mut(&mut self, index: (usize, usize)) -> &mut Self::Output {
-        let (y, x) = index;
-        unsafe { self.grid.get_unchecked_mut(y * self.width + x) }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulate::boundary::Boundary;
+
+    #[test]
+    fn wrapping_worlds_simulate_on_one_thread() {
+        let mut world = World::new(200, 50, 1);
+        let mut pixels = vec![0; 200 * 50 * 4];
+        world.simulate(1, &mut pixels);
+        assert!(world.thread_strips.len() > 1);
+
+        world.boundaries.right = Boundary::Wrap;
+        world.simulate(1, &mut pixels);
+        assert_eq!(world.thread_strips, vec![(0, 200)]);
     }
-}
-// Many places use a single index for lookups for better readability
-// Much less confusing when multiple locations are invovled
-impl Index<usize> for World {
-    type Output = Cell;
-    fn index(&self, index: usize) -> &Self::Output {
-        unsafe { self.grid.get_unchecked(index) }
+
+    #[test]
+    fn empty_worlds_simulate() {
+        for (width, height) in [(0, 0), (10, 0), (0, 10)] {
+            let mut world = World::new(width, height, 1);
+            world.boundaries.bottom = Boundary::Source(CellType::Water);
+            world.boundaries.right = Boundary::Source(CellType::Sand);
+            world.simulate(2, &mut []);
+        }
     }
-}
-impl IndexMut<usize> for World {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        unsafe { self.grid.get_unchecked_mut(index) }
+
+    #[test]
+    fn strokes_cover_the_brush_along_the_line() {
+        let mut world = World::new(64, 32, 1);
+        let mut pixels = vec![0; 64 * 32 * 4];
+        world.draw_thick_line(10, 16, 40, 16, 4, CellType::Sand, true, false, &mut pixels);
+        let at = |world: &World, x: usize, y: usize| world.grid.material(y * 64 + x);
+        for x in 7..=43 {
+            assert_eq!(at(&world, x, 16), CellType::Sand, "({x}, 16)");
+        }
+        for (x, y) in [
+            (10, 13),
+            (25, 13),
+            (40, 19),
+            (5, 16),
+            (45, 16),
+            (25, 11),
+            (25, 21),
+        ] {
+            let expected = if y == 13 || y == 19 {
+                CellType::Sand
+            } else {
+                CellType::Air
+            };
+            assert_eq!(at(&world, x, y), expected, "({x}, {y})");
+        }
+
+        // Past the edges of the world the brush is squashed onto them
+        world.draw_thick_line(-5, -5, -5, -5, 8, CellType::Stone, true, false, &mut pixels);
+        assert_eq!(at(&world, 0, 0), CellType::Stone);
+        assert_eq!(at(&world, 4, 4), CellType::Air);
     }
 }
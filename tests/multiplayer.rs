@@ -0,0 +1,110 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use snad_stack::{
+    net::{client::Client, protocol::Stroke, server::Server},
+    simulate::{
+        cells::{Cell, CellType},
+        world::World,
+    },
+};
+
+const WIDTH: usize = 80;
+const HEIGHT: usize = 60;
+
+// Polls until the client's world is the same as the server's, the server sends everything it
+// has each frame so this only waits on the network
+fn wait_for_server(server: &Server, client: &mut Client, world: &mut World) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        client.poll(world);
+        if world.save() == server.world.save() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    false
+}
+
+#[test]
+fn clients_follow_the_server() {
+    let mut server = Server::bind("127.0.0.1:0", WIDTH, HEIGHT).unwrap();
+    let address = server.local_addr().unwrap();
+
+    let mut first = Client::connect(address).unwrap();
+    // Deliberately the wrong size, the first keyframe should fix it
+    let mut first_world = World::new(20, 20, 1);
+    server.frame();
+    assert_eq!(server.client_count(), 1);
+
+    first.send_stroke(Stroke {
+        x1: 10,
+        y1: 10,
+        x2: 60,
+        y2: 10,
+        radius: 4,
+        material: CellType::Sand,
+    });
+    // Strokes arrive on another thread, so keep the server going until it has drawn it
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while (0..WIDTH * HEIGHT).all(|index| server.world.grid.material(index) != CellType::Sand) {
+        assert!(
+            Instant::now() < deadline,
+            "the server never drew the stroke"
+        );
+        server.frame();
+        thread::sleep(Duration::from_millis(5));
+    }
+    for _ in 0..5 {
+        server.frame();
+    }
+
+    let mut late = Client::connect(address).unwrap();
+    let mut late_world = World::new(WIDTH as i32, HEIGHT as i32, 1);
+    server.frame();
+    assert_eq!(server.client_count(), 2);
+    server.frame();
+
+    assert!(wait_for_server(&server, &mut first, &mut first_world));
+    assert!(wait_for_server(&server, &mut late, &mut late_world));
+    assert_eq!((first_world.width, first_world.height), (WIDTH, HEIGHT));
+    assert_eq!(first_world.save(), late_world.save());
+}
+
+#[test]
+fn clients_reconnect_to_a_restarted_server() {
+    let server = Server::bind("127.0.0.1:0", WIDTH, HEIGHT).unwrap();
+    let address = server.local_addr().unwrap();
+    let mut client = Client::connect(address).unwrap();
+    let mut world = World::new(WIDTH as i32, HEIGHT as i32, 1);
+    drop(server);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while client.connected() {
+        assert!(Instant::now() < deadline, "the client never noticed");
+        client.poll(&mut world);
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    // Back on the same address with a different world, which the client should pick up
+    let mut server = Server::bind(address, WIDTH, HEIGHT).unwrap();
+    for x in 0..WIDTH {
+        server
+            .world
+            .grid
+            .set((HEIGHT - 1) * WIDTH + x, Cell::new(CellType::Stone));
+    }
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !client.connected() || world.save() != server.world.save() {
+        assert!(
+            Instant::now() < deadline,
+            "the client never caught up again"
+        );
+        server.frame();
+        client.poll(&mut world);
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(server.client_count(), 1);
+}
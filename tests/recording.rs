@@ -0,0 +1,98 @@
+use std::fs;
+
+use snad_stack::{
+    gui::{
+        input::CenterLocation,
+        recording::{replay, Outcome, Recorder},
+    },
+    net::protocol::Stroke,
+    simulate::{cells::CellType, world::World},
+};
+
+fn simulate(world: &mut World, recorder: &mut Recorder, pixels: &mut Vec<u8>, frames: u32) {
+    pixels.resize(world.width * world.height * 4, 0);
+    for _ in 0..frames {
+        recorder.simulate(world, 2);
+        world.simulate(2, pixels);
+    }
+}
+
+fn stroke(world: &mut World, recorder: &mut Recorder, pixels: &mut [u8], stroke: Stroke) {
+    world.draw_thick_line(
+        stroke.x1,
+        stroke.y1,
+        stroke.x2,
+        stroke.y2,
+        stroke.radius as isize,
+        stroke.material,
+        true,
+        false,
+        pixels,
+    );
+    recorder.stroke(world, stroke);
+}
+
+#[test]
+fn replaying_a_recording_matches() {
+    let path = std::env::temp_dir().join(format!("snad-recording-{}.txt", std::process::id()));
+    // Wide enough that the world would simulate on several threads if it were allowed to
+    let mut world = World::new(160, 90, 1);
+    let mut pixels = vec![];
+    let mut recorder = Recorder::create(&path, &mut world, CellType::Sand).unwrap();
+
+    simulate(&mut world, &mut recorder, &mut pixels, 10);
+    let sand = Stroke {
+        x1: 20,
+        y1: 10,
+        x2: 140,
+        y2: 20,
+        radius: 5,
+        material: CellType::Sand,
+    };
+    stroke(&mut world, &mut recorder, &mut pixels, sand);
+    simulate(&mut world, &mut recorder, &mut pixels, 30);
+
+    recorder.select(&world, CellType::Water);
+    let water = Stroke {
+        x1: 80,
+        y1: 5,
+        x2: 80,
+        y2: 40,
+        radius: 8,
+        material: CellType::Water,
+    };
+    stroke(&mut world, &mut recorder, &mut pixels, water);
+    simulate(&mut world, &mut recorder, &mut pixels, 30);
+
+    world.resize(120, 100, CenterLocation::new());
+    recorder.resize(&world);
+    simulate(&mut world, &mut recorder, &mut pixels, 30);
+    recorder.finish(&world);
+
+    let text = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    let (replayed, outcome) = replay(&text).unwrap();
+    assert_eq!(outcome, Outcome::Matches);
+    assert!(replayed.save() == world.save());
+}
+
+#[test]
+fn malformed_recordings_are_refused() {
+    let start = "snad-recording 1\nseed 7\n";
+    for size in ["0 10", "10 -5", "-1 -1", "2147483647 2147483647"] {
+        assert!(replay(&format!("{start}size {size}\n")).is_err(), "{size}");
+        let resize = format!("{start}size 10 10\n0 resize {size}\n");
+        assert!(replay(&resize).is_err(), "resize {size}");
+    }
+    for steps in ["65535 1", "1 2147483647", "-1 1"] {
+        let simulate = format!("{start}size 10 10\n0 simulate {steps}\n");
+        assert!(replay(&simulate).is_err(), "simulate {steps}");
+    }
+
+    // Strokes are kept to the world and the largest brush
+    let text = format!("{start}size 40 30\n0 stroke -100000 5 2147483647 5 1000000 sand\n");
+    let (world, outcome) = replay(&text).unwrap();
+    assert_eq!(outcome, Outcome::Unfinished);
+    let sand = (0..world.grid.len()).filter(|index| world.grid.material(*index) == CellType::Sand);
+    assert!(sand.count() < world.grid.len());
+}